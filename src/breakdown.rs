@@ -0,0 +1,88 @@
+use std::collections::HashMap;
+use std::path::Path;
+use serde::Serialize;
+use crate::{DirUsage, FileUsage, Owner, ScanObserver, Usage};
+
+/// Adds up the files reported by a scan by their extension, by what they contain or by who owns them.
+///
+/// Like [`TopN`](crate::TopN), this only holds one [`Usage`] per group, not the files themselves.
+/// It sees only the files passed to observers, so [`ScanOptions::max_depth`](crate::ScanOptions::max_depth)
+/// should not be set.
+#[derive(Debug, Clone)]
+pub struct Breakdown {
+    by: GroupBy,
+    groups: HashMap<String, Usage>,
+}
+
+/// What [`Breakdown`] groups files by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    /// The extension of the file name, lowercased. Directories count their own space as `(directories)`.
+    Extension,
+    /// [`FileUsage::content`], which needs [`ScanOptions::sniff_content`](crate::ScanOptions::sniff_content).
+    /// Directories count their own space as `(directories)`.
+    Content,
+    /// The user id of the owner. Directories count their own space too.
+    Owner,
+    /// The group id. Directories count their own space too.
+    Group,
+}
+
+/// The files of one extension, kind of content or owner, see [`Breakdown`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Group {
+    /// The extension, the kind of content, or the user or group id
+    pub name: String,
+    #[serde(flatten)]
+    pub usage: Usage,
+}
+
+impl Breakdown {
+    pub fn new(by: GroupBy) -> Self {
+        Self { by, groups: HashMap::new() }
+    }
+
+    /// The groups, in no particular order.
+    pub fn into_groups(self) -> Vec<Group> {
+        self.groups.into_iter().map(|(name, usage)| Group { name, usage }).collect()
+    }
+
+    fn group_of(&self, file: &FileUsage) -> String {
+        match self.by {
+            GroupBy::Owner | GroupBy::Group => self.owner_id(file.owner),
+            // symlinks and special files have neither contents nor a meaningful extension
+            _ if file.usage.symlinks > 0 => "(symlink)".to_string(),
+            _ if file.usage.others > 0 => "(special)".to_string(),
+            GroupBy::Extension => extension(&file.path).unwrap_or_else(|| "(none)".to_string()),
+            GroupBy::Content => file.content.unwrap_or("(unreadable)").to_string(),
+        }
+    }
+
+    fn owner_id(&self, owner: Option<Owner>) -> String {
+        let id = match self.by {
+            GroupBy::Group => owner.map(|owner| owner.gid),
+            _ => owner.map(|owner| owner.uid),
+        };
+        id.map_or_else(|| "(unknown)".to_string(), |id| id.to_string())
+    }
+}
+
+impl ScanObserver for Breakdown {
+    fn on_dir(&mut self, dir: &DirUsage) {
+        // everything below was passed to on_file and on_dir already
+        let group = match self.by {
+            GroupBy::Owner | GroupBy::Group => self.owner_id(dir.owner),
+            GroupBy::Extension | GroupBy::Content => "(directories)".to_string(),
+        };
+        *self.groups.entry(group).or_default() += dir.own_usage;
+    }
+
+    fn on_file(&mut self, file: &FileUsage) {
+        let group = self.group_of(file);
+        *self.groups.entry(group).or_default() += file.usage;
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    Some(path.extension()?.to_string_lossy().to_lowercase())
+}
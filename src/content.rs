@@ -0,0 +1,131 @@
+//! Telling what a file contains from its first bytes, like `file` does, for [`ScanOptions::sniff_content`](crate::ScanOptions::sniff_content).
+
+use std::io;
+use std::path::Path;
+use tokio::io::AsyncReadExt;
+
+/// How much of a file [`sniff`] looks at. Enough for the tar header, the magic furthest in.
+const HEADER_LEN: usize = 512;
+
+/// Signatures at the start of a file, checked in order
+const MAGIC: &[(&[u8], &str)] = &[
+    (b"\x89PNG\r\n\x1a\n", "png"),
+    (b"\xff\xd8\xff", "jpeg"),
+    (b"GIF87a", "gif"),
+    (b"GIF89a", "gif"),
+    (b"%PDF-", "pdf"),
+    (b"PK\x03\x04", "zip"),
+    (b"PK\x05\x06", "zip"),
+    (b"\x1f\x8b", "gzip"),
+    (b"BZh", "bzip2"),
+    (b"\xfd7zXZ\x00", "xz"),
+    (b"\x28\xb5\x2f\xfd", "zstd"),
+    (b"7z\xbc\xaf\x27\x1c", "7z"),
+    (b"Rar!\x1a\x07", "rar"),
+    (b"\x7fELF", "elf"),
+    (b"\xcf\xfa\xed\xfe", "mach-o"),
+    (b"\xce\xfa\xed\xfe", "mach-o"),
+    (b"MZ", "pe"),
+    (b"\x00asm", "wasm"),
+    (b"\xca\xfe\xba\xbe", "java class"),
+    (b"SQLite format 3\x00", "sqlite"),
+    (b"\x1a\x45\xdf\xa3", "matroska"),
+    (b"OggS", "ogg"),
+    (b"fLaC", "flac"),
+    (b"ID3", "mp3"),
+    (b"MThd", "midi"),
+    (b"#!", "script"),
+];
+
+/// Name the kind of content `header`, the start of a file, belongs to.
+///
+/// Files that are neither recognized nor text are `"data"`.
+pub(crate) fn sniff(header: &[u8]) -> &'static str {
+    if header.is_empty() {
+        return "empty";
+    }
+    if let Some((_, kind)) = MAGIC.iter().find(|(magic, _)| header.starts_with(magic)) {
+        return kind;
+    }
+    // containers that say what they hold a few bytes in
+    match (header.get(..4), header.get(4..8), header.get(8..12)) {
+        (Some(b"RIFF"), _, Some(b"WAVE")) => return "wav",
+        (Some(b"RIFF"), _, Some(b"AVI ")) => return "avi",
+        (Some(b"RIFF"), _, Some(b"WEBP")) => return "webp",
+        (_, Some(b"ftyp"), Some(b"qt  ")) => return "quicktime",
+        (_, Some(b"ftyp"), _) => return "mp4",
+        _ => {},
+    }
+    if header.get(257..262) == Some(b"ustar") {
+        return "tar";
+    }
+    if is_text(header) { "text" } else { "data" }
+}
+
+/// Whether `header` looks like UTF-8 text. It may end in the middle of a character.
+fn is_text(header: &[u8]) -> bool {
+    let valid = match std::str::from_utf8(header) {
+        Ok(_) => header.len(),
+        // error_len is None if the input just ended too early
+        Err(err) if err.error_len().is_none() => err.valid_up_to(),
+        Err(_) => return false,
+    };
+    header[..valid].iter().all(|&byte| !byte.is_ascii_control() || byte.is_ascii_whitespace() || byte == 0x1b)
+}
+
+/// Read the start of the file at `path` and [`sniff`] it.
+pub(crate) async fn sniff_file(path: &Path) -> io::Result<&'static str> {
+    let file = tokio::fs::File::open(path).await?;
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    file.take(HEADER_LEN as u64).read_to_end(&mut header).await?;
+    Ok(sniff(&header))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn magic() {
+        assert_eq!(sniff(b""), "empty");
+        assert_eq!(sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), "png");
+        assert_eq!(sniff(b"MZ\x90\0\x03\0\0\0"), "pe");
+        assert_eq!(sniff(b"#!/bin/sh\necho hi\n"), "script");
+        assert_eq!(sniff(b"\x7fELF\x02\x01\x01"), "elf");
+        // too short to hold the whole magic
+        assert_eq!(sniff(b"\x89PN"), "data");
+    }
+
+    #[test]
+    fn containers() {
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WAVEfmt "), "wav");
+        assert_eq!(sniff(b"RIFF\x24\0\0\0WEBPVP8 "), "webp");
+        assert_eq!(sniff(b"RIFF\x24\0\0\0"), "data");
+        assert_eq!(sniff(b"\0\0\0\x18ftypisom"), "mp4");
+        assert_eq!(sniff(b"\0\0\0\x14ftypqt  "), "quicktime");
+
+        let mut tar = vec![0; HEADER_LEN];
+        tar[..5].copy_from_slice(b"a.txt");
+        tar[257..263].copy_from_slice(b"ustar\0");
+        assert_eq!(sniff(&tar), "tar");
+    }
+
+    #[test]
+    fn text() {
+        assert_eq!(sniff(b"hello\tworld\r\n"), "text");
+        assert_eq!(sniff("grüße\n".as_bytes()), "text");
+        assert_eq!(sniff(b"\x1b[1mbold\x1b[0m\n"), "text");
+        // cut off in the middle of the two bytes of "ü"
+        assert_eq!(sniff(&"grü".as_bytes()[..3]), "text");
+        assert_eq!(sniff(b"gr\xc3"), "text");
+    }
+
+    #[test]
+    fn data() {
+        assert_eq!(sniff(b"text\0with a nul"), "data");
+        assert_eq!(sniff(b"bell\x07"), "data");
+        // not a truncated character, but an invalid one
+        assert_eq!(sniff(b"gr\xc3x"), "data");
+        assert_eq!(sniff(b"\xff\xfe\xfd"), "data");
+    }
+}
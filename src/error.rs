@@ -0,0 +1,43 @@
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+
+/// What the scanner was doing when an error occurred.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ScanOperation {
+    /// Reading the metadata of an entry
+    Metadata,
+    /// Listing the entries of a directory
+    ReadDir,
+    /// Running a task that failed before it could tell which entry it was for.
+    /// The path of these errors is empty.
+    Task,
+}
+
+/// An error that kept part of the tree from being counted.
+///
+/// The scan carries on past these, so the result only covers what could be read.
+#[derive(Debug)]
+pub struct ScanError {
+    pub path: PathBuf,
+    /// Index of the scanned path, if `path` is one of them
+    pub root: Option<usize>,
+    pub operation: ScanOperation,
+    pub error: io::Error,
+}
+
+impl fmt::Display for ScanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.operation {
+            ScanOperation::Metadata => write!(f, "cannot access '{}': {}", self.path.display(), self.error),
+            ScanOperation::ReadDir => write!(f, "cannot read directory '{}': {}", self.path.display(), self.error),
+            ScanOperation::Task => write!(f, "scan task failed: {}", self.error),
+        }
+    }
+}
+
+impl std::error::Error for ScanError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        Some(&self.error)
+    }
+}
@@ -0,0 +1,129 @@
+//! `rdu --gnu`: the flags of GNU du, mapped onto [`Opts`].
+
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use clap::{ArgAction, Parser};
+use rdu::Pattern;
+use crate::size::{self, BlockSize, Threshold};
+use crate::Opts;
+
+#[derive(Parser, Debug)]
+#[command(name = "du", version, disable_help_flag = true)]
+/// Summarize disk usage of each FILE, recursively for directories, like GNU du
+pub struct GnuOpts {
+    /// Files and directories to count (default = current directory)
+    pub files: Vec<PathBuf>,
+    /// End each output line with NUL, not newline
+    #[clap(short = '0', long)]
+    pub null: bool,
+    /// Write counts for all files, not just directories
+    #[clap(short = 'a', long, conflicts_with = "summarize")]
+    pub all: bool,
+    /// Print apparent sizes rather than disk usage
+    #[clap(long)]
+    pub apparent_size: bool,
+    /// Scale sizes by SIZE before printing them, e.g. -BM prints sizes in units of 1,048,576 bytes
+    #[clap(short = 'B', long, value_name = "SIZE", value_parser = size::parse_block_size,
+        overrides_with_all = ["bytes", "human_readable", "si", "kilobytes", "megabytes"])]
+    pub block_size: Option<BlockSize>,
+    /// Equivalent to --apparent-size --block-size=1
+    #[clap(short = 'b', long, overrides_with_all = ["block_size", "human_readable", "si", "kilobytes", "megabytes"])]
+    pub bytes: bool,
+    /// Produce a grand total
+    #[clap(short = 'c', long)]
+    pub total: bool,
+    /// Dereference only symlinks that are listed on the command line
+    #[clap(short = 'H', short_alias = 'D', long, overrides_with_all = ["dereference", "no_dereference"])]
+    pub dereference_args: bool,
+    /// Print the total for a directory only if it is N or fewer levels below the command line argument
+    #[clap(short = 'd', long, value_name = "N")]
+    pub max_depth: Option<usize>,
+    /// Print sizes in human readable format (e.g., 1K 234M 2G)
+    #[clap(short = 'h', long, overrides_with_all = ["block_size", "bytes", "si", "kilobytes", "megabytes"])]
+    pub human_readable: bool,
+    /// List inode usage information instead of block usage
+    #[clap(long)]
+    pub inodes: bool,
+    /// Like --block-size=1K
+    #[clap(short = 'k', overrides_with_all = ["block_size", "bytes", "human_readable", "si", "megabytes"])]
+    pub kilobytes: bool,
+    /// Dereference all symbolic links
+    #[clap(short = 'L', long, overrides_with_all = ["dereference_args", "no_dereference"])]
+    pub dereference: bool,
+    /// Count sizes many times if hard linked
+    #[clap(short = 'l', long)]
+    pub count_links: bool,
+    /// Like --block-size=1M
+    #[clap(short = 'm', overrides_with_all = ["block_size", "bytes", "human_readable", "si", "kilobytes"])]
+    pub megabytes: bool,
+    /// Don't follow any symbolic links (this is the default)
+    #[clap(short = 'P', long, overrides_with_all = ["dereference_args", "dereference"])]
+    pub no_dereference: bool,
+    /// Display only a total for each argument
+    #[clap(short = 's', long, conflicts_with = "max_depth")]
+    pub summarize: bool,
+    /// Like -h, but use powers of 1000 not 1024
+    #[clap(long, overrides_with_all = ["block_size", "bytes", "human_readable", "kilobytes", "megabytes"])]
+    pub si: bool,
+    /// Exclude entries smaller than SIZE if positive, or entries greater than SIZE if negative
+    #[clap(short = 't', long, value_name = "SIZE", value_parser = size::parse_du_threshold, allow_hyphen_values = true)]
+    pub threshold: Option<Threshold>,
+    /// Exclude files that match any pattern in FILE
+    #[clap(short = 'X', long, value_name = "FILE")]
+    pub exclude_from: Vec<PathBuf>,
+    /// Exclude files that match PATTERN
+    #[clap(long, value_name = "PATTERN")]
+    pub exclude: Vec<Pattern>,
+    /// Skip directories on different file systems
+    #[clap(short = 'x', long)]
+    pub one_file_system: bool,
+    /// Display this help and exit
+    #[clap(long, action = ArgAction::Help)]
+    help: Option<bool>,
+}
+
+impl GnuOpts {
+    pub fn into_opts(self) -> Opts {
+        // everything du has no flag for stays at rdu's defaults
+        let mut opts = Opts::parse_from(["rdu"]);
+        opts.gnu = true;
+        opts.dir = self.files;
+        opts.null = self.null;
+        opts.all = self.all;
+        opts.apparent_size = self.apparent_size || self.bytes;
+        opts.block_size = if self.bytes { Some(BlockSize::new(1)) } else { self.block_size };
+        opts.total = self.total;
+        opts.dereference_args = self.dereference_args;
+        opts.follow_symlinks = self.dereference;
+        opts.max_depth = self.max_depth;
+        opts.human_readable = self.human_readable;
+        opts.inodes = self.inodes;
+        opts.kilobytes = self.kilobytes;
+        opts.ignore_hardlinks = self.count_links;
+        opts.megabytes = self.megabytes;
+        opts.summarize = self.summarize;
+        opts.si = self.si;
+        opts.threshold = self.threshold;
+        opts.exclude_from = self.exclude_from;
+        opts.exclude = self.exclude;
+        opts.one_file_system = self.one_file_system;
+        opts
+    }
+}
+
+/// Whether the command line asks for `--gnu`, or rdu was run as `du`.
+pub fn requested() -> bool {
+    let mut args = std::env::args_os();
+    let run_as_du = args.next().is_some_and(|arg0| Path::new(&arg0).file_stem().is_some_and(|name| name == "du"));
+    run_as_du || args.take_while(|arg| arg != "--").any(|arg| arg == "--gnu")
+}
+
+/// The command line, without `--gnu`.
+pub fn args() -> Vec<OsString> {
+    let mut args: Vec<OsString> = std::env::args_os().collect();
+    let end = args.iter().position(|arg| arg == "--").unwrap_or(args.len());
+    if let Some(gnu) = args[..end].iter().position(|arg| arg == "--gnu") {
+        args.remove(gnu);
+    }
+    args
+}
@@ -0,0 +1,60 @@
+//! Names of user and group ids, for `--by-owner` and `--by-group`.
+
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Names of the ids in a file like /etc/passwd or /etc/group, which have a `name:password:id:...` line
+/// per entry.
+///
+/// Like getpwuid, the first line of an id wins. If the file can't be read, there are no names.
+pub fn read_names(path: impl AsRef<Path>) -> HashMap<u32, String> {
+    let Ok(contents) = std::fs::read_to_string(path) else { return HashMap::new() };
+    parse_names(&contents)
+}
+
+fn parse_names(contents: &str) -> HashMap<u32, String> {
+    let mut names = HashMap::new();
+    for line in contents.lines().filter(|line| !line.starts_with('#')) {
+        let mut fields = line.split(':');
+        let (Some(name), Some(id)) = (fields.next(), fields.nth(1)) else { continue };
+        if let Ok(id) = id.parse() {
+            names.entry(id).or_insert_with(|| name.to_string());
+        }
+    }
+    names
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let names = parse_names("\
+# comment:x:7:7
+root:x:0:0:root:/root:/bin/bash
+daemon:x:1:1::/usr/sbin:/usr/sbin/nologin
+#disabled:x:2:2
+toor:x:0:0:second root:/root:/bin/sh
+no id
+broken:x:none:0
+short:x
+");
+        assert_eq!(names.len(), 2);
+        assert_eq!(names[&0], "root");
+        assert_eq!(names[&1], "daemon");
+    }
+
+    #[test]
+    fn groups() {
+        let names = parse_names("wheel:x:10:alice,bob\nusers:x:100:\n");
+        assert_eq!(names[&10], "wheel");
+        assert_eq!(names[&100], "users");
+    }
+
+    #[test]
+    fn unreadable() {
+        assert!(read_names("/nonexistent/passwd").is_empty());
+        assert!(parse_names("").is_empty());
+    }
+}
@@ -0,0 +1,32 @@
+//! Calculate space usage of a directory tree.
+//!
+//! ```no_run
+//! # async fn run() {
+//! let scanner = rdu::Scanner::new(rdu::ScanOptions::new().follow_symlinks(true));
+//! let result = scanner.scan("/var").await;
+//! println!("{}\t/var", result.total.allocated);
+//! for err in &result.errors {
+//!     eprintln!("{}", err);
+//! }
+//! # }
+//! ```
+
+mod breakdown;
+mod content;
+mod error;
+mod options;
+mod progress;
+mod scanner;
+mod top;
+mod tree;
+mod usage;
+
+pub use breakdown::{Breakdown, Group, GroupBy};
+pub use error::{ScanError, ScanOperation};
+pub use glob::Pattern;
+pub use options::{EntrySizes, ScanOptions};
+pub use progress::{Progress, ScanProgress};
+pub use scanner::{DirUsage, FileUsage, Owner, RootUsage, ScanObserver, ScanResult, ScanStats, Scanner, StopHandle, SymlinkLoop};
+pub use top::{Top, TopN};
+pub use tree::{Node, NodeKind, TreeBuilder};
+pub use usage::Usage;
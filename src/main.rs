@@ -1,147 +1,716 @@
-use clap::Parser;
+use clap::{ArgAction, CommandFactory, Parser, ValueEnum};
 use humansize::format_size;
 use humansize::FormatSizeOptions;
-use std::{env, error::Error, fs::Metadata, io, path::PathBuf};
-use std::collections::HashSet;
-use std::io::ErrorKind;
-use tokio::fs;
-#[cfg(all(not(target_os = "hermit"), unix))]
-use std::os::unix::fs::MetadataExt as MetadataExtUnix;
+use rdu::{Breakdown, DirUsage, EntrySizes, FileUsage, Group, GroupBy, Pattern, RootUsage, ScanError, ScanResult, ScanOperation, ScanObserver, ScanOptions, ScanProgress, Scanner, StopHandle, SymlinkLoop, Top, TopN, TreeBuilder, Usage};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::{env, error::Error, fmt::Display, path::{Path, PathBuf}};
+use std::io::IsTerminal;
+use std::process::ExitCode;
 use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+use size::{BlockSize, Threshold};
+
+mod gnu;
+mod ids;
+mod size;
+#[cfg(feature = "tui")]
+mod tui;
 
 #[derive(Parser, Debug, Clone)]
-#[command(author, version)]
+#[command(author, version, disable_help_flag = true)]
 /// Calculate space usage of a directory tree
 pub struct Opts {
-    /// Directory to start from (default = current directory)
-    pub dir: Option<PathBuf>,
-    #[clap(short, long)]
+    /// Directories to start from (default = current directory)
+    pub dir: Vec<PathBuf>,
+    /// Print sizes like 1.2MB
+    #[clap(short, long, overrides_with_all = ["si", "block_size", "kilobytes", "megabytes"])]
     pub human_readable: bool,
+    /// Like --human-readable, but printed like du does: powers of 1000, rounded up (1.3M)
+    #[clap(long, overrides_with_all = ["human_readable", "block_size", "kilobytes", "megabytes"])]
+    pub si: bool,
+    /// Print sizes in blocks of SIZE bytes, rounded up. -BM prints 12M, -B1M prints 12
+    #[clap(short = 'B', long, value_name = "SIZE", value_parser = size::parse_block_size,
+        overrides_with_all = ["human_readable", "si", "kilobytes", "megabytes"])]
+    pub block_size: Option<BlockSize>,
+    /// Like --block-size=1K
+    #[clap(short = 'k', overrides_with_all = ["human_readable", "si", "block_size", "megabytes"])]
+    pub kilobytes: bool,
+    /// Like --block-size=1M
+    #[clap(short = 'm', overrides_with_all = ["human_readable", "si", "block_size", "kilobytes"])]
+    pub megabytes: bool,
     #[clap(short, long)]
     pub ignore_hardlinks: bool,
     #[clap(short, long)]
     pub follow_symlinks: bool,
-    // #[clap(short, long)]
-    // pub summarize: bool,
+    /// Follow the starting directories if they are symlinks, but no symlinks below them
+    #[clap(short = 'H', long)]
+    pub dereference_args: bool,
+    /// Also print files, not only directories
+    #[clap(short = 'a', long, conflicts_with = "summarize")]
+    pub all: bool,
+    /// Only print the total for the starting directory, not every directory below it (same as --max-depth 0)
+    #[clap(short, long, conflicts_with = "max_depth")]
+    pub summarize: bool,
+    /// Only print directories at most this many levels below the starting directory.
+    /// Everything below is still counted.
+    #[clap(short = 'd', long, value_name = "N")]
+    pub max_depth: Option<usize>,
+    /// Print apparent sizes (sum of file lengths) instead of the space allocated on disk
+    #[clap(long, conflicts_with = "both")]
+    pub apparent_size: bool,
+    /// Print both the allocated and the apparent size, in that order
+    #[clap(long)]
+    pub both: bool,
+    /// Count entries instead of bytes. Prints the total followed by the number of files, directories,
+    /// symlinks and other entries.
+    #[clap(long, conflicts_with_all = ["apparent_size", "both"])]
+    pub inodes: bool,
+    /// Which kinds of entries count the space they take up themselves, besides regular files
+    #[clap(long, value_enum, value_name = "KINDS", value_delimiter = ',', default_value = "all")]
+    pub entry_sizes: Vec<EntryKind>,
+    /// Also print the sum of all directories
+    #[clap(short = 'c', long)]
+    pub total: bool,
+    /// Skip directories on different filesystems than the starting directory
+    #[clap(short = 'x', long)]
+    pub one_file_system: bool,
+    /// Print the mount points skipped by --one-file-system to stderr
+    #[clap(long, requires = "one_file_system")]
+    pub list_skipped: bool,
+    /// Skip entries whose name or path matches the glob PATTERN (can be repeated)
+    #[clap(long, value_name = "PATTERN")]
+    pub exclude: Vec<Pattern>,
+    /// Skip entries matching any pattern in FILE, one per line
+    #[clap(short = 'X', long, value_name = "FILE")]
+    pub exclude_from: Vec<PathBuf>,
+    /// Only print the N largest files and the N largest directories, largest first
+    #[clap(long, value_name = "N")]
+    pub top: Option<usize>,
+    /// Only print how much space the files of each extension, or each kind of content, take up, largest first.
+    /// Directories themselves are counted as (directories). Telling the content requires reading the start of every file.
+    #[clap(long, value_enum, value_name = "KEY", conflicts_with_all = ["top", "summarize", "max_depth"])]
+    pub breakdown: Option<BreakdownKey>,
+    /// Like --breakdown, but by the user owning the files and directories, named as in /etc/passwd
+    #[clap(long, conflicts_with_all = ["breakdown", "by_group", "top", "summarize", "max_depth"])]
+    pub by_owner: bool,
+    /// Like --by-owner, but by their group, named as in /etc/group
+    #[clap(long, conflicts_with_all = ["breakdown", "top", "summarize", "max_depth"])]
+    pub by_group: bool,
+    /// Print directories ordered by KEY once the scan is done, instead of as they finish
+    #[clap(long, value_enum, value_name = "KEY", conflicts_with_all = ["top", "breakdown", "by_owner", "by_group"])]
+    pub sort: Option<SortKey>,
+    /// Reverse the order of --sort
+    #[clap(short = 'r', long, requires = "sort")]
+    pub reverse: bool,
+    /// Only print entries of at least SIZE, or at most SIZE if negative (e.g. 100M, -1.5GiB)
+    #[clap(short = 't', long, value_name = "SIZE", value_parser = size::parse_threshold, allow_hyphen_values = true, conflicts_with = "top")]
+    pub threshold: Option<Threshold>,
+    /// Maximum number of entries being read at once
+    #[clap(short = 'j', long, value_name = "N", default_value_t = 512)]
+    pub jobs: usize,
+    /// Show a progress line on stderr while scanning (default if stderr is a terminal)
+    #[clap(long, overrides_with = "no_progress")]
+    pub progress: bool,
+    /// Never show a progress line
+    #[clap(long)]
+    pub no_progress: bool,
+    /// End lines with NUL instead of newline
+    #[clap(short = '0', long)]
+    pub null: bool,
+    /// Take the flags of GNU du instead and print like it does, in 1K blocks.
+    /// Also the case when run as `du`.
+    #[clap(long)]
+    pub gnu: bool,
+    /// Output format
+    #[clap(long, value_enum, default_value_t = Format::Text)]
+    pub format: Format,
+    /// Browse the result interactively once the scan is done, sized by the size and unit flags
+    #[cfg(feature = "tui")]
+    #[clap(long, conflicts_with_all = ["summarize", "max_depth", "format", "total", "top", "sort", "threshold", "breakdown", "by_owner", "by_group"])]
+    pub interactive: bool,
+    /// Print help (-h is taken by --human-readable)
+    #[clap(long, action = ArgAction::Help)]
+    help: Option<bool>,
 }
 
-#[cfg(feature = "mimalloc")]
-#[global_allocator]
-static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// One `size<TAB>path` line per directory, like du
+    Text,
+    /// A nested tree of all directories and files, printed once the scan is done
+    Json,
+    /// One JSON object per directory, file and error, printed as soon as they are done
+    Ndjson,
+}
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error>> {
-    let opts = Opts::parse();
-    let start_dir = match opts.dir.as_ref() {
-        Some(dir) => dir.clone(),
-        _ => env::current_dir()?,
-    };
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryKind {
+    /// Directories
+    Dirs,
+    /// Symlinks, unless followed
+    Symlinks,
+    /// Fifos, sockets and devices
+    Others,
+    /// All of the above
+    All,
+    /// Only count regular files
+    None,
+}
 
-    let usage = calc_space_usage(start_dir.clone(), opts.clone()).await?;
-    let human_usage = if opts.human_readable {
-        format_size(usage, FormatSizeOptions::default())
-    }else {
-        usage.to_string()
-    };
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BreakdownKey {
+    /// The extension of the file name
+    Extension,
+    /// What the file contains, told from its first bytes, like `file` does
+    Content,
+}
 
-    println!("{}\t{}", human_usage, start_dir.display());
-    Ok(())
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortKey {
+    /// Largest first
+    Size,
+    /// By path
+    Name,
+    /// Most files first
+    Count,
+    /// Most recently modified first, counting everything below a directory
+    Mtime,
 }
 
-async fn calc_space_usage(path: PathBuf, opts: Opts) -> Result<u64, io::Error> {
-    let meta_js:Arc<tokio::sync::Mutex<tokio::task::JoinSet<(PathBuf, std::io::Result<Metadata>)>>> = Arc::new(tokio::sync::Mutex::const_new(tokio::task::JoinSet::new()));
-    let mut dir_js:tokio::task::JoinSet<(PathBuf, std::io::Result<()>)> = tokio::task::JoinSet::new();
-    let mut hashmap:HashSet<(u64, u64)> = HashSet::new();
-    meta_js.lock().await.spawn(async{meta_with_path(path).await});
-    let mut size = 0;
-
-    loop {
-        while let Some(value) = std::future::poll_fn(|ctx|dir_js.poll_join_next(ctx)).await {
-            match value {
-                Err(err) => panic!("{}", err),
-                Ok((_, Ok(()))) => {},
-                Ok((path, Err(err))) => {
-                    handle_err(path, &meta_js, err).await?
-                },
+/// A line of text output, held back for `--sort`.
+struct Line {
+    path: PathBuf,
+    usage: Usage,
+    modified: Option<SystemTime>,
+    incomplete: bool,
+}
+
+/// A line of `--format ndjson` output.
+#[derive(Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum Record<'a> {
+    Dir(&'a DirUsage),
+    File(&'a FileUsage),
+    Group(&'a Group),
+    Error { path: String, error: String },
+    Total {
+        #[serde(flatten)]
+        usage: &'a Usage,
+        #[serde(skip_serializing_if = "std::ops::Not::not")]
+        incomplete: bool,
+    },
+}
+
+/// Something printed as JSON, with `"incomplete": true` added if the scan was interrupted.
+#[derive(Serialize)]
+struct Marked<T> {
+    #[serde(flatten)]
+    item: T,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    incomplete: bool,
+}
+
+impl Record<'_> {
+    fn print(&self) {
+        match serde_json::to_string(self) {
+            Ok(line) => println!("{}", line),
+            Err(err) => eprintln!("rdu: cannot serialize record: {}", err),
+        }
+    }
+}
+
+impl Opts {
+    pub fn scan_options(&self) -> Result<ScanOptions, Box<dyn Error>> {
+        let mut scan_options = ScanOptions::new()
+            .ignore_hardlinks(self.ignore_hardlinks)
+            .follow_symlinks(self.follow_symlinks)
+            .dereference_args(self.dereference_args)
+            .roots_in_order(self.gnu)
+            .entry_sizes(self.entry_sizes())
+            .sniff_content(self.breakdown == Some(BreakdownKey::Content))
+            .max_depth(if self.summarize { Some(0) } else { self.max_depth })
+            .one_file_system(self.one_file_system)
+            .jobs(self.jobs);
+        for pattern in &self.exclude {
+            scan_options = scan_options.exclude(pattern.clone());
+        }
+        for file in &self.exclude_from {
+            let patterns = std::fs::read_to_string(file)
+                .map_err(|err| format!("cannot read '{}': {}", file.display(), err))?;
+            for line in patterns.lines().filter(|line| !line.is_empty()) {
+                let pattern = Pattern::new(line)
+                    .map_err(|err| format!("invalid pattern '{}' in '{}': {}", line, file.display(), err))?;
+                scan_options = scan_options.exclude(pattern);
             }
         }
-        if meta_js.lock().await.is_empty() {
-            match dir_js.join_next().await {
-                None => break,
-                Some(Err(err)) => panic!("{}", err),
-                Some(Ok((_, Ok(())))) => {},
-                Some(Ok((path, Err(err)))) => {
-                    handle_err(path, &meta_js, err).await?
-                },
+        Ok(scan_options)
+    }
+
+    fn entry_sizes(&self) -> EntrySizes {
+        let mut entry_sizes = EntrySizes::NONE;
+        for kind in &self.entry_sizes {
+            match kind {
+                EntryKind::Dirs => entry_sizes.dirs = true,
+                EntryKind::Symlinks => entry_sizes.symlinks = true,
+                EntryKind::Others => entry_sizes.others = true,
+                EntryKind::All => entry_sizes = EntrySizes::ALL,
+                EntryKind::None => {},
             }
         }
-        match meta_js.lock().await.join_next().await {
-            None => break,
-            Some(Ok((path, Err(meta)))) => {
-                handle_err(path, &meta_js, meta).await?;
+        entry_sizes
+    }
+
+    /// What `--breakdown`, `--by-owner` or `--by-group` group by, if given.
+    fn group_by(&self) -> Option<GroupBy> {
+        if self.by_owner {
+            Some(GroupBy::Owner)
+        } else if self.by_group {
+            Some(GroupBy::Group)
+        } else {
+            self.breakdown.map(|key| match key {
+                BreakdownKey::Extension => GroupBy::Extension,
+                BreakdownKey::Content => GroupBy::Content,
+            })
+        }
+    }
+
+    fn show_progress(&self) -> bool {
+        if self.progress || self.no_progress {
+            self.progress
+        } else {
+            std::io::stderr().is_terminal()
+        }
+    }
+
+    /// The size `--top`, `--sort` and `--threshold` go by.
+    fn size(&self, usage: &Usage) -> u64 {
+        if self.inodes {
+            usage.inodes()
+        } else if self.apparent_size {
+            usage.apparent
+        } else {
+            usage.allocated
+        }
+    }
+
+    fn is_shown(&self, usage: &Usage) -> bool {
+        self.threshold.is_none_or(|threshold| threshold.admits(self.size(usage)))
+    }
+
+    fn sort(&self, lines: &mut [Line]) {
+        match self.sort {
+            Some(SortKey::Size) => lines.sort_by_key(|line| std::cmp::Reverse(self.size(&line.usage))),
+            Some(SortKey::Name) => lines.sort_by(|a, b| a.path.cmp(&b.path)),
+            Some(SortKey::Count) => lines.sort_by_key(|line| std::cmp::Reverse(line.usage.files)),
+            Some(SortKey::Mtime) => lines.sort_by_key(|line| std::cmp::Reverse(line.modified)),
+            None => return,
+        }
+        if self.reverse {
+            lines.reverse();
+        }
+    }
+
+    /// Print a line of text output.
+    fn print_line(&self, usage: &Usage, path: impl Display) {
+        print!("{}\t{}{}", self.format_usage(usage), path, if self.null { '\0' } else { '\n' });
+    }
+
+    /// Print the line of a directory or file, marked if the scan was stopped before all of it was counted.
+    /// Not with --gnu, whose lines have to stay the way scripts reading du's output expect them.
+    fn print_path_line(&self, usage: &Usage, path: &Path, incomplete: bool) {
+        if incomplete && !self.gnu {
+            self.print_line(usage, format_args!("{} (incomplete)", path.display()));
+        } else {
+            self.print_line(usage, path.display());
+        }
+    }
+
+    fn format_usage(&self, usage: &Usage) -> String {
+        if self.inodes && self.gnu {
+            usage.inodes().to_string()
+        } else if self.inodes {
+            format!("{}\t{}\t{}\t{}\t{}", usage.inodes(), usage.files, usage.dirs, usage.symlinks, usage.others)
+        } else if self.both {
+            format!("{}\t{}", self.format_size(usage.allocated), self.format_size(usage.apparent))
+        } else if self.apparent_size {
+            self.format_size(usage.apparent)
+        } else {
+            self.format_size(usage.allocated)
+        }
+    }
+
+    fn format_size(&self, size: u64) -> String {
+        if self.human_readable && self.gnu {
+            size::format_binary(size)
+        } else if self.human_readable {
+            format_size(size, FormatSizeOptions::default())
+        } else if self.si {
+            size::format_si(size)
+        } else if self.kilobytes {
+            BlockSize::new(1 << 10).format(size)
+        } else if self.megabytes {
+            BlockSize::new(1 << 20).format(size)
+        } else if let Some(block_size) = &self.block_size {
+            block_size.format(size)
+        } else if self.gnu {
+            BlockSize::new(1 << 10).format(size)
+        } else {
+            size.to_string()
+        }
+    }
+}
+
+/// Prints every directory below the starting directory as they finish, or collects them for `--format json`,
+/// `--top` or `--breakdown`.
+struct DirPrinter<'a> {
+    opts: &'a Opts,
+    tree: TreeBuilder,
+    top: Option<TopN>,
+    breakdown: Option<Breakdown>,
+    /// Everything to print for `--sort`, starting directories included
+    lines: Vec<Line>,
+    /// Whether stderr has a progress line, which has to be cleared before printing anything else there
+    progress: bool,
+    /// Whether something failed that isn't a [`ScanError`], like a symlink loop with --gnu
+    failed: bool,
+}
+
+impl ScanObserver for DirPrinter<'_> {
+    fn on_dir(&mut self, dir: &DirUsage) {
+        if let Some(top) = &mut self.top {
+            return top.on_dir(dir);
+        }
+        if let Some(breakdown) = &mut self.breakdown {
+            return breakdown.on_dir(dir);
+        }
+        if self.opts.format != Format::Json && !self.opts.is_shown(&dir.usage) { return; }
+        match self.opts.format {
+            Format::Text if self.opts.sort.is_some() => {
+                self.lines.push(Line { path: dir.path.clone(), usage: dir.usage, modified: dir.modified, incomplete: dir.incomplete });
             },
-            Some(Ok((path, Ok(meta)))) => {
-                #[cfg(all(not(target_os = "hermit"), unix))]
-                {
-                    //we only track hardlinks, if !opts.ignore_hardlinks. If opts.ignore_hardlinks, we just count them duplicate.
-                    //
-                    //if meta.nlink == 1, then we know we don't have multiple hardlinked files.
-                    //in that case we don't even need to save that inode number.
-                    if !opts.ignore_hardlinks && meta.nlink() > 1 && !hashmap.insert((meta.dev(), meta.ino())) { continue };
-                }
-                let file_type = meta.file_type();
-
-                if file_type.is_file() {
-                    size += meta.len();
-                } else if file_type.is_dir() {
-                    let meta_js = meta_js.clone();
-                    dir_js.spawn(async move {
-                        let path_clone = path.clone();
-                        let inner = || async {
-                            let mut entries = fs::read_dir(path_clone).await?;
-                            let mut vec = Vec::new();
-                            while let Some(entry) = entries.next_entry().await? {
-                                vec.push(async move { meta_with_path(entry.path()).await });
-                            }
-                            let mut mutex = meta_js.lock().await;
-                            for fut in vec {
-                                mutex.spawn(fut);
-                            }
-                            drop(mutex);
-                            Ok::<(), std::io::Error>(())
-                        };
-                        (path, inner().await)
-                    });
-                } else if opts.follow_symlinks && file_type.is_symlink() {
-                    meta_js.lock().await.spawn(async move {
-                        let meta = fs::metadata(&path).await;
-                        (path, meta)
-                    });
-                } else if !opts.follow_symlinks && file_type.is_symlink() {
-                    // don't follow symlinks
-                }
+            // --gnu scans the starting directories one after the other, so like du, they are printed as they finish.
+            // otherwise main prints them from the final result, in the order they were given
+            Format::Text if dir.depth == 0 && !self.opts.gnu => {},
+            Format::Text => self.opts.print_path_line(&dir.usage, &dir.path, dir.incomplete),
+            Format::Json => self.tree.on_dir(dir),
+            Format::Ndjson => Record::Dir(dir).print(),
+        }
+    }
+
+    fn on_file(&mut self, file: &FileUsage) {
+        if let Some(top) = &mut self.top {
+            return top.on_file(file);
+        }
+        if let Some(breakdown) = &mut self.breakdown {
+            return breakdown.on_file(file);
+        }
+        if self.opts.format != Format::Json && !self.opts.is_shown(&file.usage) { return; }
+        match self.opts.format {
+            // a starting path that is a file, or --all
+            Format::Text if self.opts.sort.is_some() && (file.depth == 0 || self.opts.all) => {
+                self.lines.push(Line { path: file.path.clone(), usage: file.usage, modified: file.modified, incomplete: false });
+            },
+            // the starting paths are printed by main, unless with --gnu
+            Format::Text if self.opts.all && file.depth > 0 || self.opts.gnu && file.depth == 0 => {
+                self.opts.print_line(&file.usage, file.path.display());
             },
-            Some(Err(err)) => {
-                panic!("{}", err)
+            Format::Text => {},
+            Format::Json => self.tree.on_file(file),
+            Format::Ndjson => Record::File(file).print(),
+        }
+    }
+
+    fn on_error(&mut self, err: &ScanError) {
+        if self.progress { clear_progress(); }
+        eprintln!("rdu: {}", err);
+        match self.opts.format {
+            Format::Text => {},
+            Format::Json if self.top.is_none() && self.breakdown.is_none() => self.tree.on_error(err),
+            Format::Json => {},
+            Format::Ndjson => Record::Error { path: err.path.to_string_lossy().into_owned(), error: err.to_string() }.print(),
+        }
+    }
+
+    fn on_mount_skipped(&mut self, path: &Path) {
+        if self.opts.list_skipped {
+            if self.progress { clear_progress(); }
+            eprintln!("rdu: skipping mount point '{}'", path.display());
+        }
+    }
+
+    fn on_symlink_loop(&mut self, path: &Path, kind: SymlinkLoop) {
+        if self.progress { clear_progress(); }
+        // du fails on a symlink it can't resolve, like on any other entry it can't read
+        if self.opts.gnu && kind == SymlinkLoop::Unresolvable {
+            eprintln!("rdu: cannot access '{}': Too many levels of symbolic links", path.display());
+            self.failed = true;
+            return;
+        }
+        eprintln!("rdu: warning: not following '{}', it leads to a symlink loop", path.display());
+    }
+}
+
+/// Redraw a progress line on stderr a few times a second, until aborted.
+fn spawn_progress(progress: Arc<ScanProgress>, opts: Opts) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let start = Instant::now();
+        let mut interval = tokio::time::interval(Duration::from_millis(250));
+        loop {
+            interval.tick().await;
+            let progress = progress.snapshot();
+            let rate = progress.entries as f64 / start.elapsed().as_secs_f64().max(0.001);
+            let current = progress.current.to_string_lossy();
+            // keep the end of the path, that's the part that changes
+            let skip = current.chars().count().saturating_sub(60);
+            let current: String = current.chars().skip(skip).collect();
+            eprint!(
+                "\r\x1b[K{} entries, {} dirs pending, {} counted, {:.0} entries/s, {}{}",
+                progress.entries,
+                progress.pending_dirs,
+                opts.format_usage(&progress.usage),
+                rate,
+                if skip > 0 { "..." } else { "" },
+                current,
+            );
+        }
+    })
+}
+
+/// Stop the task started by [`spawn_progress`] and remove its line.
+async fn stop_progress(progress: Option<tokio::task::JoinHandle<()>>) {
+    let Some(progress) = progress else { return };
+    progress.abort();
+    // wait for it, so it can't draw again after the line is cleared
+    let _ = progress.await;
+    clear_progress();
+}
+
+fn clear_progress() {
+    eprint!("\r\x1b[K");
+}
+
+/// Stop the scan on the first SIGINT and exit on the second one. Print the total so far on SIGUSR1.
+fn spawn_signal_handler(stop: StopHandle, progress: Arc<ScanProgress>, opts: Opts, show_progress: bool) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut dump = DumpSignal::new();
+        loop {
+            tokio::select! {
+                Ok(()) = tokio::signal::ctrl_c() => {
+                    if show_progress { clear_progress(); }
+                    if stop.is_stopped() {
+                        eprintln!("rdu: interrupted");
+                        std::process::exit(130);
+                    }
+                    stop.stop();
+                    eprintln!("rdu: interrupted, finishing entries already found (press Ctrl-C again to quit right away)");
+                },
+                () = dump.recv() => {
+                    let progress = progress.snapshot();
+                    if show_progress { clear_progress(); }
+                    eprintln!("rdu: {}\tcounted so far in {} entries", opts.format_usage(&progress.usage), progress.entries);
+                },
             }
         }
+    })
+}
+
+/// SIGUSR1, where there is such a thing.
+#[cfg(unix)]
+struct DumpSignal(Option<tokio::signal::unix::Signal>);
+
+#[cfg(unix)]
+impl DumpSignal {
+    fn new() -> Self {
+        Self(tokio::signal::unix::signal(tokio::signal::unix::SignalKind::user_defined1()).ok())
+    }
+
+    async fn recv(&mut self) {
+        if let Some(signal) = &mut self.0 {
+            if signal.recv().await.is_some() { return; }
+            self.0 = None;
+        }
+        std::future::pending().await
+    }
+}
+
+#[cfg(not(unix))]
+struct DumpSignal;
+
+#[cfg(not(unix))]
+impl DumpSignal {
+    fn new() -> Self {
+        Self
     }
 
-    Ok(size)
+    async fn recv(&mut self) {
+        std::future::pending().await
+    }
+}
+
+/// Print the sum of everything if asked for, and if the scan was interrupted, marked as incomplete.
+///
+/// Not asked for, it is only printed if interrupted without --gnu, which has to print what du would.
+fn print_total(opts: &Opts, result: &ScanResult) {
+    let marked = result.interrupted && !opts.gnu;
+    if !opts.total && !marked { return; }
+    match opts.format {
+        Format::Text if marked => opts.print_line(&result.total, "total (incomplete)"),
+        Format::Text => opts.print_line(&result.total, "total"),
+        // the output is marked itself
+        Format::Json => {},
+        Format::Ndjson => Record::Total { usage: &result.total, incomplete: result.interrupted }.print(),
+    }
 }
 
-async fn handle_err(path: PathBuf, meta_js:&tokio::sync::Mutex<tokio::task::JoinSet<(PathBuf, std::io::Result<Metadata>)>>, err: std::io::Error) -> std::io::Result<()>{
-    match err.kind(){
-        ErrorKind::NotFound => Ok(()),
-        ErrorKind::OutOfMemory => {
-            meta_js.lock().await.spawn(async{meta_with_path(path).await});
-            Ok(())
+/// Print the report of `--top`.
+fn print_top(opts: &Opts, top: Top, result: &ScanResult) -> Result<(), Box<dyn Error>> {
+    match opts.format {
+        Format::Text => {
+            println!("largest files:");
+            for file in &top.files {
+                opts.print_line(&file.usage, file.path.display());
+            }
+            println!("largest directories:");
+            for dir in &top.dirs {
+                opts.print_path_line(&dir.usage, &dir.path, dir.incomplete);
+            }
+        },
+        Format::Json => println!("{}", serde_json::to_string(&Marked { item: &top, incomplete: result.interrupted })?),
+        Format::Ndjson => {
+            top.files.iter().for_each(|file| Record::File(file).print());
+            top.dirs.iter().for_each(|dir| Record::Dir(dir).print());
         },
-        _ => return Err(err),
     }
+    print_total(opts, result);
+    Ok(())
 }
 
-async fn meta_with_path(path: PathBuf) -> (PathBuf, io::Result<Metadata>) {
-    let meta = fs::symlink_metadata(&path).await;
-    (path, meta)
+/// Print the report of `--breakdown`, `--by-owner` or `--by-group`.
+fn print_breakdown(opts: &Opts, breakdown: Breakdown, result: &ScanResult) -> Result<(), Box<dyn Error>> {
+    let mut groups: Vec<Group> = breakdown.into_groups().into_iter().filter(|group| opts.is_shown(&group.usage)).collect();
+    let names = if opts.by_owner {
+        ids::read_names("/etc/passwd")
+    } else if opts.by_group {
+        ids::read_names("/etc/group")
+    } else {
+        HashMap::new()
+    };
+    // ids without a name stay numbers, like ls prints them
+    for group in &mut groups {
+        if let Some(name) = group.name.parse().ok().and_then(|id: u32| names.get(&id)) {
+            group.name = name.clone();
+        }
+    }
+    groups.sort_by(|a, b| opts.size(&b.usage).cmp(&opts.size(&a.usage)).then_with(|| a.name.cmp(&b.name)));
+    match opts.format {
+        Format::Text => {
+            for group in &groups {
+                if opts.inodes {
+                    opts.print_line(&group.usage, &group.name);
+                } else {
+                    // the number of files, to tell a few large files from many small ones
+                    opts.print_line(&group.usage, format_args!("{}\t{}", group.usage.files, group.name));
+                }
+            }
+        },
+        Format::Json => {
+            let groups: Vec<_> = groups.iter().map(|group| Marked { item: group, incomplete: result.interrupted }).collect();
+            println!("{}", serde_json::to_string(&groups)?);
+        },
+        Format::Ndjson => groups.iter().for_each(|group| Record::Group(group).print()),
+    }
+    print_total(opts, result);
+    Ok(())
+}
+
+#[cfg(feature = "mimalloc")]
+#[global_allocator]
+static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
+
+#[tokio::main]
+async fn main() -> Result<ExitCode, Box<dyn Error>> {
+    let opts = if gnu::requested() { gnu::GnuOpts::parse_from(gnu::args()).into_opts() } else { Opts::parse() };
+    if opts.sort.is_some() && opts.format != Format::Text {
+        Opts::command().error(clap::error::ErrorKind::ArgumentConflict, "--sort only applies to --format text").exit();
+    }
+    if opts.threshold.is_some() && opts.format == Format::Json {
+        Opts::command().error(clap::error::ErrorKind::ArgumentConflict, "--threshold doesn't apply to --format json").exit();
+    }
+    let start_dirs = if opts.dir.is_empty() {
+        vec![env::current_dir()?]
+    } else {
+        opts.dir.clone()
+    };
+
+    let scanner = Scanner::new(opts.scan_options()?);
+    let show_progress = opts.show_progress();
+    let progress = show_progress.then(|| spawn_progress(scanner.progress(), opts.clone()));
+    spawn_signal_handler(scanner.stop_handle(), scanner.progress(), opts.clone(), show_progress);
+
+    #[cfg(feature = "tui")]
+    if opts.interactive {
+        let mut tree = TreeBuilder::new();
+        let result = scanner.scan_all_with(start_dirs, &mut tree).await;
+        stop_progress(progress).await;
+        for err in &result.errors {
+            eprintln!("rdu: {}", err);
+        }
+        if result.interrupted {
+            eprintln!("rdu: scan was interrupted, sizes are incomplete");
+        }
+        tui::run(&scanner, tree.into_roots(), &opts).await?;
+        return Ok(if result.is_complete() { ExitCode::SUCCESS } else { ExitCode::FAILURE });
+    }
+
+    let top = opts.top.map(|n| if opts.inodes {
+        TopN::by_key(n, Usage::inodes)
+    } else {
+        TopN::new(n, opts.apparent_size)
+    });
+    let breakdown = opts.group_by().map(Breakdown::new);
+    let mut printer = DirPrinter { opts: &opts, tree: TreeBuilder::new(), top, breakdown, lines: Vec::new(), progress: show_progress, failed: false };
+    let result = scanner.scan_all_with(start_dirs, &mut printer).await;
+    stop_progress(progress).await;
+    if result.stats.retries > 0 {
+        eprintln!("rdu: retried reading {} entries after running out of file descriptors or memory", result.stats.retries);
+    }
+
+    if let Some(top) = printer.top.take() {
+        print_top(&opts, top.into_top(), &result)?;
+    } else if let Some(breakdown) = printer.breakdown.take() {
+        print_breakdown(&opts, breakdown, &result)?;
+    } else {
+        match opts.format {
+            Format::Text if opts.sort.is_some() => {
+                opts.sort(&mut printer.lines);
+                for line in &printer.lines {
+                    opts.print_path_line(&line.usage, &line.path, line.incomplete);
+                }
+            },
+            // printed as they finished
+            Format::Text if opts.gnu => {},
+            Format::Text => {
+                // like du, skip the paths that couldn't be read at all, were counted through an earlier one or are excluded
+                let missing = |root: &&RootUsage| result.errors.iter().any(|err| err.operation == ScanOperation::Metadata && err.path == root.path);
+                let skipped = |root: &&RootUsage| missing(root) || root.already_counted || root.excluded;
+                for root in result.roots.iter().filter(|root| opts.is_shown(&root.usage) && !skipped(root)) {
+                    opts.print_path_line(&root.usage, &root.path, root.incomplete);
+                }
+            },
+            Format::Json => {
+                // every directory not counted in full is marked itself
+                println!("{}", serde_json::to_string(&printer.tree.into_roots())?);
+            },
+            Format::Ndjson => {},
+        }
+        print_total(&opts, &result);
+    }
+    if result.interrupted {
+        eprintln!("rdu: scan was interrupted, the sizes above are incomplete");
+        // what a shell reports for a process killed by SIGINT
+        return Ok(ExitCode::from(130));
+    }
+    // like du, signal that the total above is missing whatever could not be read
+    Ok(if result.is_complete() && !printer.failed { ExitCode::SUCCESS } else { ExitCode::FAILURE })
 }
@@ -0,0 +1,150 @@
+use std::fs::Metadata;
+use glob::Pattern;
+
+/// Options controlling how a [`Scanner`](crate::Scanner) walks a directory tree.
+///
+/// Built with chained setters, starting from [`ScanOptions::new`]:
+/// ```
+/// let opts = rdu::ScanOptions::new().ignore_hardlinks(true);
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ScanOptions {
+    pub(crate) ignore_hardlinks: bool,
+    pub(crate) follow_symlinks: bool,
+    pub(crate) dereference_args: bool,
+    pub(crate) roots_in_order: bool,
+    pub(crate) max_depth: Option<usize>,
+    pub(crate) one_file_system: bool,
+    pub(crate) excludes: Vec<Pattern>,
+    pub(crate) jobs: Option<usize>,
+    pub(crate) entry_sizes: EntrySizes,
+    pub(crate) sniff_content: bool,
+}
+
+impl ScanOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count every hardlink of a file, instead of only the first one seen.
+    pub fn ignore_hardlinks(mut self, ignore_hardlinks: bool) -> Self {
+        self.ignore_hardlinks = ignore_hardlinks;
+        self
+    }
+
+    /// Descend into the targets of symlinks, instead of skipping them.
+    ///
+    /// Everything reachable through several symlinks is only counted once, like hardlinks.
+    pub fn follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
+
+    /// Follow the scanned paths themselves if they are symlinks, but no symlinks below them.
+    pub fn dereference_args(mut self, dereference_args: bool) -> Self {
+        self.dereference_args = dereference_args;
+        self
+    }
+
+    /// Scan the paths one after another, in the order given, instead of all at once.
+    ///
+    /// Entries reachable from several paths, like hardlinks, are then counted for the first of them, like du does.
+    pub fn roots_in_order(mut self, roots_in_order: bool) -> Self {
+        self.roots_in_order = roots_in_order;
+        self
+    }
+
+    /// Only report directories and files at most `max_depth` levels below the scanned path.
+    ///
+    /// Deeper entries are still counted towards their parents, they are just not passed to
+    /// the [`ScanObserver`](crate::ScanObserver).
+    pub fn max_depth(mut self, max_depth: Option<usize>) -> Self {
+        self.max_depth = max_depth;
+        self
+    }
+
+    /// Don't descend into directories on a different filesystem than the scanned path.
+    ///
+    /// Skipped mount points are reported to
+    /// [`ScanObserver::on_mount_skipped`](crate::ScanObserver::on_mount_skipped) and not counted.
+    pub fn one_file_system(mut self, one_file_system: bool) -> Self {
+        self.one_file_system = one_file_system;
+        self
+    }
+
+    /// Skip entries whose file name or full path matches `pattern`.
+    ///
+    /// Excluded entries are dropped while their directory is listed, so nothing below them is
+    /// ever read. The scanned paths are skipped too if they match, see [`RootUsage::excluded`](crate::RootUsage::excluded).
+    /// Can be called several times, an entry is skipped if it matches any pattern.
+    pub fn exclude(mut self, pattern: Pattern) -> Self {
+        self.excludes.push(pattern);
+        self
+    }
+
+    /// Maximum number of entries being read or waiting to be counted at once (default 512).
+    ///
+    /// Directories are only listed as fast as their entries get counted, and at most this many at once.
+    /// The others wait their turn, the ones found last first, so the scan goes deep before it goes wide
+    /// and memory use grows with how many subdirectories directories have, not with the size of the tree.
+    pub fn jobs(mut self, jobs: usize) -> Self {
+        self.jobs = Some(jobs);
+        self
+    }
+
+    /// Which kinds of entries count the space they use themselves, besides regular files.
+    ///
+    /// By default all of them do, like du. The others are still counted as entries.
+    pub fn entry_sizes(mut self, entry_sizes: EntrySizes) -> Self {
+        self.entry_sizes = entry_sizes;
+        self
+    }
+
+    /// Read the start of every regular file to tell what it contains, see [`FileUsage::content`](crate::FileUsage::content).
+    ///
+    /// This opens every file, which makes the scan a lot slower.
+    pub fn sniff_content(mut self, sniff_content: bool) -> Self {
+        self.sniff_content = sniff_content;
+        self
+    }
+}
+
+/// Which kinds of entries take up space, see [`ScanOptions::entry_sizes`].
+///
+/// Regular files always do.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EntrySizes {
+    /// The blocks holding a directory's list of entries
+    pub dirs: bool,
+    /// Symlinks not followed
+    pub symlinks: bool,
+    /// Fifos, sockets and devices
+    pub others: bool,
+}
+
+impl EntrySizes {
+    /// Count the space of every kind of entry.
+    pub const ALL: Self = Self { dirs: true, symlinks: true, others: true };
+    /// Only count the space of regular files.
+    pub const NONE: Self = Self { dirs: false, symlinks: false, others: false };
+
+    /// Whether the entry `meta` describes takes up space.
+    pub(crate) fn counts(&self, meta: &Metadata) -> bool {
+        let file_type = meta.file_type();
+        if file_type.is_file() {
+            true
+        } else if file_type.is_dir() {
+            self.dirs
+        } else if file_type.is_symlink() {
+            self.symlinks
+        } else {
+            self.others
+        }
+    }
+}
+
+impl Default for EntrySizes {
+    fn default() -> Self {
+        Self::ALL
+    }
+}
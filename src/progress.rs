@@ -0,0 +1,91 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use crate::Usage;
+
+/// Live counters of a running scan, see [`Scanner::progress`](crate::Scanner::progress).
+///
+/// Updated by the scan as it goes and safe to read from anywhere at any time, e.g. from a task
+/// that redraws a progress line every now and then.
+#[derive(Debug, Default)]
+pub struct ScanProgress {
+    entries: AtomicU64,
+    pending_dirs: AtomicU64,
+    apparent: AtomicU64,
+    allocated: AtomicU64,
+    files: AtomicU64,
+    dirs: AtomicU64,
+    symlinks: AtomicU64,
+    others: AtomicU64,
+    current: Mutex<PathBuf>,
+}
+
+/// The state of a scan at some point, see [`ScanProgress::snapshot`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Progress {
+    /// Number of entries whose metadata was read
+    pub entries: u64,
+    /// Number of directories waiting to be listed, or being listed right now
+    pub pending_dirs: u64,
+    /// Everything counted so far
+    pub usage: Usage,
+    /// The directory most recently found
+    pub current: PathBuf,
+}
+
+impl ScanProgress {
+    pub fn snapshot(&self) -> Progress {
+        Progress {
+            entries: self.entries.load(Ordering::Relaxed),
+            pending_dirs: self.pending_dirs.load(Ordering::Relaxed),
+            usage: Usage {
+                apparent: self.apparent.load(Ordering::Relaxed),
+                allocated: self.allocated.load(Ordering::Relaxed),
+                files: self.files.load(Ordering::Relaxed),
+                dirs: self.dirs.load(Ordering::Relaxed),
+                symlinks: self.symlinks.load(Ordering::Relaxed),
+                others: self.others.load(Ordering::Relaxed),
+            },
+            current: self.current.lock().map(|current| current.clone()).unwrap_or_default(),
+        }
+    }
+
+    pub(crate) fn reset(&self) {
+        self.entries.store(0, Ordering::Relaxed);
+        self.pending_dirs.store(0, Ordering::Relaxed);
+        self.apparent.store(0, Ordering::Relaxed);
+        self.allocated.store(0, Ordering::Relaxed);
+        self.files.store(0, Ordering::Relaxed);
+        self.dirs.store(0, Ordering::Relaxed);
+        self.symlinks.store(0, Ordering::Relaxed);
+        self.others.store(0, Ordering::Relaxed);
+        if let Ok(mut current) = self.current.lock() {
+            current.clear();
+        }
+    }
+
+    pub(crate) fn entry_done(&self) {
+        self.entries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn counted(&self, usage: Usage) {
+        self.apparent.fetch_add(usage.apparent, Ordering::Relaxed);
+        self.allocated.fetch_add(usage.allocated, Ordering::Relaxed);
+        self.files.fetch_add(usage.files, Ordering::Relaxed);
+        self.dirs.fetch_add(usage.dirs, Ordering::Relaxed);
+        self.symlinks.fetch_add(usage.symlinks, Ordering::Relaxed);
+        self.others.fetch_add(usage.others, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_pending_dirs(&self, pending_dirs: usize) {
+        self.pending_dirs.store(pending_dirs as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn set_current(&self, path: &Path) {
+        if let Ok(mut current) = self.current.lock() {
+            // reuse the allocation, this happens for every directory
+            current.clear();
+            current.push(path);
+        }
+    }
+}
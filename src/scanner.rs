@@ -0,0 +1,896 @@
+use std::{fs::Metadata, io, path::{Path, PathBuf}};
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::io::ErrorKind;
+use std::panic::AssertUnwindSafe;
+use std::task::Poll;
+use tokio::fs;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+use tokio::task::{JoinError, JoinSet};
+#[cfg(all(not(target_os = "hermit"), unix))]
+use std::os::unix::fs::MetadataExt as MetadataExtUnix;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, SystemTime};
+use glob::Pattern;
+use serde::Serialize;
+use crate::{content, ScanError, ScanOperation, ScanOptions, ScanProgress, Usage};
+
+type DirId = usize;
+type MetaJoinSet = JoinSet<(Entry, io::Result<Metadata>)>;
+type SharedMetaJoinSet = Arc<tokio::sync::Mutex<MetaJoinSet>>;
+type DirJoinSet = JoinSet<Listed>;
+
+/// Default for [`ScanOptions::jobs`]. More than tokio's blocking pool runs at once wouldn't help.
+const DEFAULT_JOBS: usize = 512;
+/// How often reading an entry is retried after running out of file descriptors or memory, before giving up
+const MAX_RETRIES: u32 = 10;
+
+/// Walks a directory tree and adds up the space used by it.
+#[derive(Debug, Clone, Default)]
+pub struct Scanner {
+    opts: ScanOptions,
+    progress: Arc<ScanProgress>,
+    stop: StopHandle,
+}
+
+/// Ends a running scan early, see [`Scanner::stop_handle`].
+#[derive(Debug, Clone, Default)]
+pub struct StopHandle(Arc<AtomicBool>);
+
+impl StopHandle {
+    /// Stop listing directories. Entries found already are still counted, then the scan returns
+    /// with [`ScanResult::interrupted`] set.
+    pub fn stop(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_stopped(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Outcome of a [`Scanner::scan`] call.
+#[derive(Debug)]
+pub struct ScanResult {
+    /// One entry per scanned path, in the order they were passed in
+    pub roots: Vec<RootUsage>,
+    /// Sum of all `roots`
+    pub total: Usage,
+    /// Everything that could not be read. If this is not empty, the usages are incomplete.
+    pub errors: Vec<ScanError>,
+    /// Whether the scan was stopped through a [`StopHandle`] before it was done.
+    /// If so, the usages only cover what was found until then.
+    pub interrupted: bool,
+    pub stats: ScanStats,
+}
+
+/// Counters about how a scan went, that don't affect its result.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ScanStats {
+    /// Number of times reading an entry was retried, after running out of memory or file descriptors
+    pub retries: u64,
+}
+
+/// Space used by one of the paths a scan was started from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RootUsage {
+    pub path: PathBuf,
+    /// Total space used by all files below `path`.
+    /// Files hardlinked from several roots only count towards the first root they are found in.
+    pub usage: Usage,
+    /// Whether `path` itself was counted already, through an earlier path that is the same or contains it.
+    /// `usage` is empty then.
+    pub already_counted: bool,
+    /// Whether `path` matches one of the [`ScanOptions::exclude`] patterns, and was skipped
+    pub excluded: bool,
+    /// Whether part of `path` wasn't counted, because the scan was stopped, see [`DirUsage::incomplete`]
+    pub incomplete: bool,
+}
+
+impl ScanResult {
+    /// Whether every entry below the scanned paths was counted.
+    pub fn is_complete(&self) -> bool {
+        self.errors.is_empty() && !self.interrupted
+    }
+}
+
+/// Space used by a directory, including everything below it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DirUsage {
+    #[serde(serialize_with = "crate::tree::serialize_path")]
+    pub path: PathBuf,
+    /// Number of levels below the scanned path (the scanned paths themselves are depth 0)
+    pub depth: usize,
+    /// Total space used by all files below `path`
+    #[serde(flatten)]
+    pub usage: Usage,
+    /// Latest modification time of the directory or anything below it
+    #[serde(rename = "mtime", serialize_with = "crate::tree::serialize_time", skip_serializing_if = "Option::is_none")]
+    pub modified: Option<SystemTime>,
+    /// Space used by the directory itself, not counting anything below it
+    #[serde(skip)]
+    pub own_usage: Usage,
+    /// Owner of the directory itself
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Owner>,
+    /// Index of the scanned path, if this is one of them
+    #[serde(skip)]
+    pub root: Option<usize>,
+    /// Whether part of what is below wasn't counted, because the scan was stopped before it was listed
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
+}
+
+/// Space used by a single entry that isn't a directory: a file, or a symlink or special file
+/// that wasn't followed.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct FileUsage {
+    #[serde(serialize_with = "crate::tree::serialize_path")]
+    pub path: PathBuf,
+    /// Number of levels below the scanned path (the scanned paths themselves are depth 0)
+    pub depth: usize,
+    #[serde(flatten)]
+    pub usage: Usage,
+    #[serde(rename = "mtime", serialize_with = "crate::tree::serialize_time", skip_serializing_if = "Option::is_none")]
+    pub modified: Option<SystemTime>,
+    /// What a regular file contains, see [`ScanOptions::sniff_content`]. `None` if it couldn't be read.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub content: Option<&'static str>,
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub owner: Option<Owner>,
+    /// Index of the scanned path, if this is one of them
+    #[serde(skip)]
+    pub root: Option<usize>,
+}
+
+/// User and group an entry belongs to. Only known on unix.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize)]
+pub struct Owner {
+    pub uid: u32,
+    pub gid: u32,
+}
+
+/// Receives results while a scan is still running.
+pub trait ScanObserver {
+    /// Called once a directory and everything below it has been counted.
+    ///
+    /// Directories are always reported after all of their subdirectories.
+    fn on_dir(&mut self, _dir: &DirUsage) {}
+
+    /// Called once for every file counted, before the directory it is in.
+    ///
+    /// Symlinks that aren't followed and special files are reported here too.
+    fn on_file(&mut self, _file: &FileUsage) {}
+
+    /// Called for every entry that could not be read. The scan continues afterwards.
+    fn on_error(&mut self, _err: &ScanError) {}
+
+    /// Called for every directory skipped because it is on another filesystem,
+    /// see [`ScanOptions::one_file_system`].
+    fn on_mount_skipped(&mut self, _path: &Path) {}
+
+    /// Called for every symlink not followed because it leads back to a directory it is in, or
+    /// through other symlinks back to itself, see [`ScanOptions::follow_symlinks`].
+    fn on_symlink_loop(&mut self, _path: &Path, _kind: SymlinkLoop) {}
+}
+
+/// How a symlink leads back to itself, see [`ScanObserver::on_symlink_loop`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymlinkLoop {
+    /// It leads to a directory it is in, which is counted already
+    Ancestor,
+    /// It leads through other symlinks, or directly, back to itself, so it doesn't point to anything
+    Unresolvable,
+}
+
+impl ScanObserver for () {}
+
+impl Scanner {
+    pub fn new(opts: ScanOptions) -> Self {
+        Self { opts, progress: Arc::default(), stop: StopHandle::default() }
+    }
+
+    pub fn options(&self) -> &ScanOptions {
+        &self.opts
+    }
+
+    /// Live counters of the scan currently running, reset whenever a new scan starts.
+    ///
+    /// Clones of a scanner share these, so only run one scan at a time per scanner if you need them.
+    pub fn progress(&self) -> Arc<ScanProgress> {
+        self.progress.clone()
+    }
+
+    /// A handle to stop the scan currently running, e.g. from a signal handler.
+    ///
+    /// Like [`Scanner::progress`], this is shared by clones of a scanner. Starting a scan clears it.
+    pub fn stop_handle(&self) -> StopHandle {
+        self.stop.clone()
+    }
+
+    /// Scan the tree rooted at `path`.
+    ///
+    /// Entries that vanish during the scan are ignored. Entries that cannot be read are skipped
+    /// and collected in [`ScanResult::errors`].
+    pub async fn scan(&self, path: impl Into<PathBuf>) -> ScanResult {
+        self.scan_with(path, &mut ()).await
+    }
+
+    /// Like [`Scanner::scan`], but reports every directory and error to `observer` as soon as it happens.
+    pub async fn scan_with<O: ScanObserver + ?Sized>(&self, path: impl Into<PathBuf>, observer: &mut O) -> ScanResult {
+        self.scan_all_with([path], observer).await
+    }
+
+    /// Scan several trees at once.
+    ///
+    /// All trees share one set of seen hardlinks, so a file is only counted once even if it is
+    /// reachable from several paths. If a path is the same as or inside another one, the paths are
+    /// scanned one after the other instead, so everything below them is counted for the first of them.
+    pub async fn scan_all_with<O: ScanObserver + ?Sized>(&self, paths: impl IntoIterator<Item = impl Into<PathBuf>>, observer: &mut O) -> ScanResult {
+        let paths: Vec<PathBuf> = paths.into_iter().map(Into::into).collect();
+        self.progress.reset();
+        self.stop.0.store(false, Ordering::Relaxed);
+        let (roots, errors, stats) = calc_space_usage(paths, &self.opts, &self.progress, &self.stop, observer).await;
+        let total = roots.iter().fold(Usage::default(), |total, root| total + root.usage);
+        ScanResult { roots, total, errors, interrupted: self.stop.is_stopped(), stats }
+    }
+}
+
+/// Everything a `read_dir` task needs, besides the directory itself.
+#[derive(Debug, Clone)]
+struct Listing {
+    meta_js: SharedMetaJoinSet,
+    /// Signalled whenever something is spawned into `meta_js`
+    spawned: Arc<Notify>,
+    /// One permit per entry spawned but not yet counted
+    jobs: Arc<Semaphore>,
+    /// One permit per directory being listed
+    dirs: Arc<Semaphore>,
+    /// Number of permits `dirs` was created with, minus those taken away by [`Listing::throttle`]
+    open_dirs: Arc<AtomicUsize>,
+    excludes: Arc<[Pattern]>,
+    stop: StopHandle,
+    sniff: bool,
+}
+
+impl Listing {
+    /// Allow one directory less to be listed at once, but always at least one.
+    fn throttle(&self) {
+        let shrunk = self.open_dirs.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |limit| (limit > 1).then(|| limit - 1));
+        if shrunk.is_ok() {
+            // the permit may be in use right now, so take it away as soon as it is returned
+            let dirs = self.dirs.clone();
+            tokio::spawn(async move {
+                if let Ok(permit) = dirs.acquire_owned().await {
+                    permit.forget();
+                }
+            });
+        }
+    }
+
+    fn is_excluded(&self, path: &Path) -> bool {
+        self.excludes.iter().any(|pattern| {
+            pattern.matches_path(path) || path.file_name().is_some_and(|name| pattern.matches(&name.to_string_lossy()))
+        })
+    }
+}
+
+/// A directory waiting for a [`Listing::dirs`] permit to be listed, see [`spawn_queued`].
+#[derive(Debug)]
+struct QueuedDir {
+    id: DirId,
+    path: PathBuf,
+    depth: usize,
+    /// How often listing this directory was tried before
+    attempt: u32,
+}
+
+/// What a `read_dir` task reports back.
+#[derive(Debug)]
+struct Listed {
+    id: DirId,
+    path: PathBuf,
+    depth: usize,
+    /// How often listing this directory was tried before
+    attempt: u32,
+    /// Number of entries spawned
+    children: usize,
+    /// Whether the scan was stopped before all entries were spawned
+    stopped: bool,
+    /// Whether all entries could be read
+    result: io::Result<()>,
+}
+
+/// Where the usage of an entry is added to, once it is counted.
+#[derive(Debug, Clone, Copy)]
+enum Parent {
+    /// The entry is the `n`th scanned path
+    Root(usize),
+    Dir(DirId),
+}
+
+impl Parent {
+    fn root(self) -> Option<usize> {
+        match self {
+            Parent::Root(root) => Some(root),
+            Parent::Dir(_) => None,
+        }
+    }
+}
+
+/// An entry waiting for its metadata.
+#[derive(Debug)]
+struct Entry {
+    path: PathBuf,
+    parent: Parent,
+    depth: usize,
+    /// Held until the entry is counted. The scanned paths themselves don't need one.
+    _permit: Option<OwnedSemaphorePermit>,
+    /// Whether to read what the entry points to if it is a symlink, instead of the symlink itself
+    follow: bool,
+    /// Set by [`meta_with_path`] if it was asked to sniff a regular file
+    content: Option<&'static str>,
+    /// How often reading the metadata was retried so far
+    attempt: u32,
+    /// How often sniffing the content was retried, see [`ScanStats::retries`]
+    sniff_retries: u32,
+}
+
+/// A directory that still has entries being listed or counted.
+#[derive(Debug)]
+struct PendingDir {
+    path: PathBuf,
+    parent: Parent,
+    depth: usize,
+    /// Device the directory is on
+    dev: u64,
+    ino: u64,
+    usage: Usage,
+    /// Usage of the directory alone
+    own_usage: Usage,
+    owner: Option<Owner>,
+    /// Latest modification time of the directory and the children counted so far
+    modified: Option<SystemTime>,
+    /// Children spawned but not counted yet. May dip below 0 while the listing is still being reported.
+    pending: i64,
+    listed: bool,
+    /// Whether this directory, or one below it, wasn't listed in full because the scan was stopped
+    incomplete: bool,
+}
+
+#[derive(Debug, Default)]
+struct Tree {
+    dirs: HashMap<DirId, PendingDir>,
+    /// Deepest level passed to the observer
+    max_depth: Option<usize>,
+    next_id: DirId,
+    /// Usage of each scanned path, once it is done
+    roots: Vec<RootUsage>,
+    errors: Vec<ScanError>,
+    stats: ScanStats,
+}
+
+impl Tree {
+    /// Add a directory, with the `usage` of the directory itself.
+    fn insert(&mut self, entry: Entry, meta: &Metadata, usage: Usage) -> DirId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.dirs.insert(id, PendingDir {
+            path: entry.path,
+            parent: entry.parent,
+            depth: entry.depth,
+            dev: device(meta),
+            ino: inode(meta),
+            usage,
+            own_usage: usage,
+            owner: owner(meta),
+            modified: meta.modified().ok(),
+            pending: 0,
+            listed: false,
+            incomplete: false,
+        });
+        id
+    }
+
+    /// Whether an entry on `dev` is on another filesystem than its parent directory.
+    fn crosses_device(&self, parent: Parent, dev: u64) -> bool {
+        match parent {
+            Parent::Root(_) => false,
+            Parent::Dir(id) => self.dirs.get(&id).is_some_and(|dir| dir.dev != dev),
+        }
+    }
+
+    /// Whether the directory `dev`/`ino` is `parent` or one of its ancestors.
+    fn is_ancestor(&self, mut parent: Parent, dev: u64, ino: u64) -> bool {
+        // the ancestors of a directory can't be done before it is, so they are all still here
+        while let Parent::Dir(id) = parent {
+            let Some(dir) = self.dirs.get(&id) else { return false };
+            if dir.dev == dev && dir.ino == ino { return true; }
+            parent = dir.parent;
+        }
+        false
+    }
+
+    /// Mark the directory `id` and all its ancestors as not counted in full.
+    fn mark_incomplete(&mut self, id: DirId) {
+        let mut parent = Parent::Dir(id);
+        while let Parent::Dir(id) = parent {
+            let Some(dir) = self.dirs.get_mut(&id) else { return };
+            dir.incomplete = true;
+            parent = dir.parent;
+        }
+        if let Parent::Root(root) = parent {
+            self.roots[root].incomplete = true;
+        }
+    }
+
+    fn listed<O: ScanObserver + ?Sized>(&mut self, id: DirId, children: usize, observer: &mut O) {
+        if let Some(dir) = self.dirs.get_mut(&id) {
+            dir.listed = true;
+            dir.pending += children as i64;
+        }
+        self.try_finish(id, observer);
+    }
+
+    /// Account for a finished child of `parent`.
+    fn child_done<O: ScanObserver + ?Sized>(&mut self, parent: Parent, usage: Usage, modified: Option<SystemTime>, observer: &mut O) {
+        let id = match parent {
+            Parent::Root(root) => {
+                self.roots[root].usage = usage;
+                return;
+            },
+            Parent::Dir(id) => id,
+        };
+        if let Some(dir) = self.dirs.get_mut(&id) {
+            dir.usage += usage;
+            dir.modified = dir.modified.max(modified);
+            dir.pending -= 1;
+        }
+        self.try_finish(id, observer);
+    }
+
+    fn is_reported(&self, depth: usize) -> bool {
+        self.max_depth.is_none_or(|max_depth| depth <= max_depth)
+    }
+
+    fn file<O: ScanObserver + ?Sized>(&mut self, entry: Entry, meta: &Metadata, usage: Usage, observer: &mut O) {
+        let modified = meta.modified().ok();
+        if self.is_reported(entry.depth) {
+            observer.on_file(&FileUsage {
+                path: entry.path,
+                depth: entry.depth,
+                usage,
+                modified,
+                content: entry.content,
+                owner: owner(meta),
+                root: entry.parent.root(),
+            });
+        }
+        self.child_done(entry.parent, usage, modified, observer);
+    }
+
+    fn error<O: ScanObserver + ?Sized>(&mut self, path: PathBuf, root: Option<usize>, operation: ScanOperation, error: io::Error, observer: &mut O) {
+        let err = ScanError { path, root, operation, error };
+        observer.on_error(&err);
+        self.errors.push(err);
+    }
+
+    /// Record a task that failed without returning its entry.
+    ///
+    /// Tasks catch their own panics, so this only happens if a task is cancelled. Whatever it was
+    /// working on is never counted, [`Tree::flush`] finishes the directories waiting for it.
+    fn task_failed<O: ScanObserver + ?Sized>(&mut self, err: JoinError, observer: &mut O) {
+        self.error(PathBuf::new(), None, ScanOperation::Task, io::Error::other(err.to_string()), observer);
+    }
+
+    /// Finish all directories still waiting for entries, deepest first.
+    fn flush<O: ScanObserver + ?Sized>(&mut self, observer: &mut O) {
+        let mut ids: Vec<(usize, DirId)> = self.dirs.iter().map(|(id, dir)| (dir.depth, *id)).collect();
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+        for (_, id) in ids {
+            if let Some(dir) = self.dirs.get_mut(&id) {
+                dir.listed = true;
+                dir.pending = 0;
+            }
+            self.try_finish(id, observer);
+        }
+    }
+
+    fn try_finish<O: ScanObserver + ?Sized>(&mut self, id: DirId, observer: &mut O) {
+        match self.dirs.get(&id) {
+            Some(dir) if dir.listed && dir.pending == 0 => {},
+            _ => return,
+        }
+        let Some(dir) = self.dirs.remove(&id) else { return };
+        if self.is_reported(dir.depth) {
+            observer.on_dir(&DirUsage {
+                path: dir.path,
+                depth: dir.depth,
+                usage: dir.usage,
+                modified: dir.modified,
+                own_usage: dir.own_usage,
+                owner: dir.owner,
+                root: dir.parent.root(),
+                incomplete: dir.incomplete,
+            });
+        }
+        self.child_done(dir.parent, dir.usage, dir.modified, observer);
+    }
+}
+
+async fn calc_space_usage<O: ScanObserver + ?Sized>(paths: Vec<PathBuf>, opts: &ScanOptions, progress: &ScanProgress, stop: &StopHandle, observer: &mut O) -> (Vec<RootUsage>, Vec<ScanError>, ScanStats) {
+    let meta_js: SharedMetaJoinSet = Arc::new(tokio::sync::Mutex::const_new(JoinSet::new()));
+    let mut dir_js: DirJoinSet = JoinSet::new();
+    let mut queue: Vec<QueuedDir> = Vec::new();
+    let mut hashmap:HashSet<(u64, u64)> = HashSet::new();
+    let jobs = opts.jobs.unwrap_or(DEFAULT_JOBS).max(1);
+    let listing = Listing {
+        meta_js: meta_js.clone(),
+        spawned: Arc::new(Notify::new()),
+        jobs: Arc::new(Semaphore::new(jobs)),
+        dirs: Arc::new(Semaphore::new(jobs)),
+        open_dirs: Arc::new(AtomicUsize::new(jobs)),
+        excludes: opts.excludes.clone().into(),
+        stop: stop.clone(),
+        sniff: opts.sniff_content,
+    };
+    let excluded: Vec<bool> = paths.iter().map(|path| listing.is_excluded(path)).collect();
+    let mut tree = Tree {
+        max_depth: opts.max_depth,
+        roots: paths.iter().zip(&excluded).map(|(path, &excluded)| RootUsage {
+            path: path.clone(),
+            usage: Usage::default(),
+            already_counted: false,
+            excluded,
+            incomplete: false,
+        }).collect(),
+        ..Tree::default()
+    };
+    // scanned at once, a path inside another one could claim entries before the enclosing one gets to them
+    let overlapping = overlap(&paths).await;
+    let mut roots = paths.into_iter().enumerate().filter(|(root, _)| !excluded[*root]);
+    let root_meta = |(root, path): (usize, PathBuf)| {
+        let entry = Entry { path, parent: Parent::Root(root), depth: 0, _permit: None, follow: opts.dereference_args, content: None, attempt: 0, sniff_retries: 0 };
+        let sniff = opts.sniff_content;
+        async move {meta_with_path(entry, sniff).await}
+    };
+    if !opts.roots_in_order && !overlapping {
+        let mut mutex = meta_js.lock().await;
+        roots.by_ref().for_each(|root| { mutex.spawn(root_meta(root)); });
+    }
+
+    loop {
+        spawn_queued(&mut queue, &mut dir_js, &listing).await;
+        progress.set_pending_dirs(dir_js.len() + queue.len());
+        //directories still being listed may spawn more entries, even if there are none right now
+        if dir_js.is_empty() && meta_js.lock().await.is_empty() {
+            // only left if scanned in order, once everything of the path before is counted
+            let Some(root) = roots.next() else { break };
+            meta_js.lock().await.spawn(root_meta(root));
+        }
+        let value = tokio::select! {
+            Some(value) = dir_js.join_next() => {
+                match value {
+                    Err(err) => tree.task_failed(err, observer),
+                    Ok(listed) => handle_listed(listed, &listing, &mut queue, &mut tree, observer),
+                }
+                continue;
+            },
+            value = next_meta(&meta_js, &listing.spawned) => value,
+        };
+        progress.entry_done();
+        match value {
+            Ok((mut entry, Err(err))) => {
+                match err.kind() {
+                    // vanished since its directory was listed, but the scanned paths have to exist
+                    ErrorKind::NotFound if entry.depth > 0 => tree.child_done(entry.parent, Usage::default(), None, observer),
+                    // symlinks that lead to each other
+                    _ if opts.follow_symlinks && is_symlink_loop(&err) => {
+                        observer.on_symlink_loop(&entry.path, SymlinkLoop::Unresolvable);
+                        tree.child_done(entry.parent, Usage::default(), None, observer);
+                    },
+                    ErrorKind::OutOfMemory if entry.attempt < MAX_RETRIES => {
+                        tree.stats.retries += 1;
+                        entry.attempt += 1;
+                        let sniff = opts.sniff_content;
+                        meta_js.lock().await.spawn(async move {meta_with_path(entry, sniff).await});
+                    },
+                    _ => {
+                        tree.error(entry.path, entry.parent.root(), ScanOperation::Metadata, err, observer);
+                        tree.child_done(entry.parent, Usage::default(), None, observer);
+                    },
+                }
+            },
+            Ok((mut entry, Ok(meta))) => {
+                tree.stats.retries += u64::from(entry.sniff_retries);
+                #[cfg(all(not(target_os = "hermit"), unix))]
+                {
+                    //a followed symlink may lead to a directory we are in, which would never end.
+                    //those are the only loops, so there is no need to remember more than the current path.
+                    if opts.follow_symlinks && meta.is_dir() && tree.is_ancestor(entry.parent, meta.dev(), meta.ino()) {
+                        observer.on_symlink_loop(&entry.path, SymlinkLoop::Ancestor);
+                        tree.child_done(entry.parent, Usage::default(), None, observer);
+                        continue
+                    }
+                    //we only track hardlinks, if !opts.ignore_hardlinks. If opts.ignore_hardlinks, we just count them duplicate.
+                    //
+                    //if meta.nlink == 1, then we know we don't have multiple hardlinked files.
+                    //in that case we don't even need to save that inode number.
+                    //unless we follow symlinks, several of which may point to the same entry,
+                    //or a path is inside another one, where anything may be reached twice.
+                    let shared = meta.nlink() > 1 || opts.follow_symlinks || overlapping;
+                    if !opts.ignore_hardlinks && shared && !hashmap.insert((meta.dev(), meta.ino())) {
+                        if let Parent::Root(root) = entry.parent {
+                            tree.roots[root].already_counted = true;
+                        }
+                        tree.child_done(entry.parent, Usage::default(), None, observer);
+                        continue
+                    };
+                }
+                let file_type = meta.file_type();
+
+                if file_type.is_file() {
+                    let usage = Usage::of(&meta);
+                    progress.counted(usage);
+                    tree.file(entry, &meta, usage, observer);
+                } else if file_type.is_dir() {
+                    let dev = device(&meta);
+                    if opts.one_file_system && tree.crosses_device(entry.parent, dev) {
+                        observer.on_mount_skipped(&entry.path);
+                        tree.child_done(entry.parent, Usage::default(), None, observer);
+                        continue;
+                    }
+                    let depth = entry.depth;
+                    let path = entry.path.clone();
+                    progress.set_current(&path);
+                    let usage = if opts.entry_sizes.counts(&meta) { Usage::of(&meta) } else { Usage::entry(&meta) };
+                    progress.counted(usage);
+                    let id = tree.insert(entry, &meta, usage);
+                    if stop.is_stopped() {
+                        // counts the directory itself, but nothing below it
+                        tree.mark_incomplete(id);
+                        tree.listed(id, 0, observer);
+                    } else {
+                        queue.push(QueuedDir { id, path, depth, attempt: 0 });
+                    }
+                } else if opts.follow_symlinks && file_type.is_symlink() {
+                    let sniff = opts.sniff_content;
+                    entry.follow = true;
+                    meta_js.lock().await.spawn(async move {meta_with_path(entry, sniff).await});
+                } else {
+                    // symlinks not followed, fifos, sockets, devices
+                    let usage = if opts.entry_sizes.counts(&meta) { Usage::of(&meta) } else { Usage::entry(&meta) };
+                    progress.counted(usage);
+                    tree.file(entry, &meta, usage, observer);
+                }
+            },
+            Err(err) => tree.task_failed(err, observer),
+        }
+    }
+    // only left over if a task failed without telling which entry it was for
+    tree.flush(observer);
+
+    (tree.roots, tree.errors, tree.stats)
+}
+
+/// Whether any of `paths` is the same as or inside another one, once symlinks are resolved.
+async fn overlap(paths: &[PathBuf]) -> bool {
+    if paths.len() < 2 { return false; }
+    let mut resolved = Vec::with_capacity(paths.len());
+    for path in paths {
+        // a path that can't be resolved fails later, when it is scanned
+        resolved.push(fs::canonicalize(path).await.unwrap_or_else(|_| path.clone()));
+    }
+    resolved.iter().enumerate().any(|(i, a)| resolved[i + 1..].iter().any(|b| a.starts_with(b) || b.starts_with(a)))
+}
+
+/// Wait for the next metadata task to finish, even if none are running yet.
+async fn next_meta(meta_js: &tokio::sync::Mutex<MetaJoinSet>, spawned: &Notify) -> Result<(Entry, io::Result<Metadata>), JoinError> {
+    loop {
+        let mut mutex = meta_js.lock().await;
+        if let Some(value) = mutex.join_next().await {
+            return value;
+        }
+        drop(mutex);
+        spawned.notified().await;
+    }
+}
+
+/// Start listing as many directories of `queue` as there are [`Listing::dirs`] permits for, the ones found last first.
+///
+/// Waiting here instead of in a task keeps wide trees from piling up tasks, and going deep first
+/// keeps the queue short.
+async fn spawn_queued(queue: &mut Vec<QueuedDir>, dir_js: &mut DirJoinSet, listing: &Listing) {
+    while let Some(dir) = queue.pop() {
+        let permit = match listing.dirs.clone().try_acquire_owned() {
+            Ok(permit) => Some(permit),
+            // nothing being listed could hand a permit back, so it can only be a moment
+            Err(_) if dir_js.is_empty() => listing.dirs.clone().acquire_owned().await.ok(),
+            Err(_) => None,
+        };
+        let Some(permit) = permit else {
+            queue.push(dir);
+            return;
+        };
+        spawn_read_dir(dir_js, listing, dir, permit);
+    }
+}
+
+/// List the directory `dir` and spawn a metadata task for every entry in it, that isn't excluded.
+///
+/// Entries are spawned while the directory is being read, but only as long as there are
+/// [`ScanOptions::jobs`] permits left, so huge directories don't pile up tasks.
+///
+/// Retries (`attempt` > 0) wait a little before starting, longer with every attempt.
+///
+/// Once the scan is stopped, the rest of the directory is left out.
+fn spawn_read_dir(dir_js: &mut DirJoinSet, listing: &Listing, dir: QueuedDir, permit: OwnedSemaphorePermit) {
+    let listing = listing.clone();
+    let QueuedDir { id, path, depth, attempt } = dir;
+    dir_js.spawn(async move {
+        let _permit = permit;
+        if attempt > 0 && !listing.stop.is_stopped() {
+            tokio::time::sleep(Duration::from_millis(10 << attempt.min(7))).await;
+        }
+        let mut children = 0;
+        let mut stopped = false;
+        let inner = async {
+            if listing.stop.is_stopped() {
+                stopped = true;
+                return Ok(());
+            }
+            let mut entries = fs::read_dir(&path).await?;
+            while let Some(entry) = entries.next_entry().await? {
+                let path = entry.path();
+                if listing.is_excluded(&path) { continue; }
+                let permit = listing.jobs.clone().acquire_owned().await.ok();
+                //waiting for the permit may take a while
+                if listing.stop.is_stopped() {
+                    stopped = true;
+                    break;
+                }
+                let entry = Entry { path, parent: Parent::Dir(id), depth: depth + 1, _permit: permit, follow: false, content: None, attempt: 0, sniff_retries: 0 };
+                let sniff = listing.sniff;
+                listing.meta_js.lock().await.spawn(async move { meta_with_path(entry, sniff).await });
+                listing.spawned.notify_one();
+                children += 1;
+            }
+            Ok::<(), std::io::Error>(())
+        };
+        let result = catch_panic(inner).await;
+        Listed { id, path, depth, attempt, children, stopped, result }
+    });
+}
+
+fn handle_listed<O: ScanObserver + ?Sized>(listed: Listed, listing: &Listing, queue: &mut Vec<QueuedDir>, tree: &mut Tree, observer: &mut O) {
+    let Listed { id, path, depth, attempt, children, stopped, result } = listed;
+    if stopped {
+        tree.mark_incomplete(id);
+    }
+    let Err(err) = result else {
+        tree.listed(id, children, observer);
+        return;
+    };
+    match err.kind(){
+        ErrorKind::NotFound => tree.listed(id, children, observer),
+        //retrying is only safe if nothing was spawned yet, otherwise those entries would be counted twice
+        ErrorKind::OutOfMemory if children == 0 && attempt < MAX_RETRIES => {
+            tree.stats.retries += 1;
+            queue.push(QueuedDir { id, path, depth, attempt: attempt + 1 });
+        },
+        _ if is_out_of_fds(&err) && children == 0 && attempt < MAX_RETRIES => {
+            tree.stats.retries += 1;
+            listing.throttle();
+            queue.push(QueuedDir { id, path, depth, attempt: attempt + 1 });
+        },
+        _ => {
+            let root = tree.dirs.get(&id).and_then(|dir| dir.parent.root());
+            tree.error(path, root, ScanOperation::ReadDir, err, observer);
+            tree.listed(id, children, observer);
+        },
+    }
+}
+
+/// Whether `err` means that this process (EMFILE) or the whole system (ENFILE) has no file descriptors left.
+#[cfg(unix)]
+fn is_out_of_fds(err: &io::Error) -> bool {
+    matches!(err.raw_os_error(), Some(libc::EMFILE | libc::ENFILE))
+}
+
+#[cfg(not(unix))]
+fn is_out_of_fds(_err: &io::Error) -> bool {
+    false
+}
+
+/// Whether `err` means that resolving a path took too many symlinks, usually because they form a loop.
+#[cfg(unix)]
+fn is_symlink_loop(err: &io::Error) -> bool {
+    err.raw_os_error() == Some(libc::ELOOP)
+}
+
+#[cfg(not(unix))]
+fn is_symlink_loop(_err: &io::Error) -> bool {
+    false
+}
+
+#[cfg(all(not(target_os = "hermit"), unix))]
+fn device(meta: &Metadata) -> u64 {
+    meta.dev()
+}
+
+#[cfg(not(all(not(target_os = "hermit"), unix)))]
+fn device(_meta: &Metadata) -> u64 {
+    0
+}
+
+#[cfg(all(not(target_os = "hermit"), unix))]
+fn inode(meta: &Metadata) -> u64 {
+    meta.ino()
+}
+
+#[cfg(not(all(not(target_os = "hermit"), unix)))]
+fn inode(_meta: &Metadata) -> u64 {
+    0
+}
+
+#[cfg(all(not(target_os = "hermit"), unix))]
+fn owner(meta: &Metadata) -> Option<Owner> {
+    Some(Owner { uid: meta.uid(), gid: meta.gid() })
+}
+
+#[cfg(not(all(not(target_os = "hermit"), unix)))]
+fn owner(_meta: &Metadata) -> Option<Owner> {
+    None
+}
+
+/// Read the metadata of `entry`, or of what it points to if it is a symlink and [`Entry::follow`] is set.
+///
+/// With `sniff`, regular files are also opened to tell what they contain.
+async fn meta_with_path(mut entry: Entry, sniff: bool) -> (Entry, io::Result<Metadata>) {
+    let meta = if entry.follow {
+        catch_panic(fs::metadata(&entry.path)).await
+    } else {
+        catch_panic(fs::symlink_metadata(&entry.path)).await
+    };
+    if sniff && meta.as_ref().is_ok_and(|meta| meta.is_file()) {
+        (entry.content, entry.sniff_retries) = sniff_content(&entry.path).await;
+    }
+    (entry, meta)
+}
+
+/// Sniff the file at `path`, waiting for file descriptors to free up if there are none left.
+///
+/// A file that can't be read is still counted, it just has no content. Also returns how often it was retried.
+async fn sniff_content(path: &Path) -> (Option<&'static str>, u32) {
+    let mut attempt = 0;
+    loop {
+        match catch_panic(content::sniff_file(path)).await {
+            Ok(content) => return (Some(content), attempt),
+            Err(err) if is_out_of_fds(&err) && attempt < MAX_RETRIES => {
+                attempt += 1;
+                tokio::time::sleep(Duration::from_millis(10 << attempt.min(7))).await;
+            },
+            Err(_) => return (None, attempt),
+        }
+    }
+}
+
+/// Run `fut`, turning a panic into an io error.
+///
+/// Tasks wrap their work in this, so that a panic is reported for the entry the task was working
+/// on, instead of an anonymous [`JoinError`].
+async fn catch_panic<T>(fut: impl Future<Output = io::Result<T>>) -> io::Result<T> {
+    let mut fut = std::pin::pin!(fut);
+    std::future::poll_fn(|ctx| {
+        match std::panic::catch_unwind(AssertUnwindSafe(|| fut.as_mut().poll(ctx))) {
+            Ok(poll) => poll,
+            Err(payload) => {
+                let msg = payload.downcast_ref::<&str>().copied()
+                    .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+                    .unwrap_or("unknown panic");
+                Poll::Ready(Err(io::Error::other(format!("task panicked: {}", msg))))
+            },
+        }
+    }).await
+}
@@ -0,0 +1,253 @@
+//! Parsing sizes given on the command line.
+
+/// Parse a size like `100M`, `1.5GB` or `4KiB`, in bytes.
+///
+/// Units are read the way `--human-readable` prints them: `k`, `M`, `G`, ... are powers of 1000,
+/// `KiB`, `MiB`, `GiB`, ... powers of 1024. Case and a trailing `B` don't matter.
+pub fn parse_size(size: &str) -> Result<u64, String> {
+    let size = size.trim();
+    let split = size.find(|c: char| !c.is_ascii_digit() && c != '.').unwrap_or(size.len());
+    let (number, unit) = size.split_at(split);
+    let multiplier = unit_multiplier(unit.trim())
+        .ok_or_else(|| format!("unknown unit '{}' in size '{}'", unit.trim(), size))?;
+    if let Ok(number) = number.parse::<u64>() {
+        return number.checked_mul(multiplier).ok_or_else(|| format!("size '{}' is too large", size));
+    }
+    let number: f64 = number.parse().map_err(|_| format!("invalid size '{}'", size))?;
+    let bytes = (number * multiplier as f64).round();
+    if bytes >= u64::MAX as f64 {
+        return Err(format!("size '{}' is too large", size));
+    }
+    Ok(bytes as u64)
+}
+
+/// Which sizes `--threshold` lets through.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Threshold {
+    AtLeast(u64),
+    AtMost(u64),
+}
+
+impl Threshold {
+    pub fn admits(self, size: u64) -> bool {
+        match self {
+            Threshold::AtLeast(threshold) => size >= threshold,
+            Threshold::AtMost(threshold) => size <= threshold,
+        }
+    }
+}
+
+/// Parse a `--threshold`: a size, negative to mean "at most" instead of "at least".
+pub fn parse_threshold(threshold: &str) -> Result<Threshold, String> {
+    match threshold.trim().strip_prefix('-') {
+        Some(size) => parse_size(size).map(Threshold::AtMost),
+        None => parse_size(threshold).map(Threshold::AtLeast),
+    }
+}
+
+fn unit_multiplier(unit: &str) -> Option<u64> {
+    let unit = unit.to_ascii_lowercase();
+    let (prefix, base) = match unit.strip_suffix("ib") {
+        Some(prefix) => (prefix, 1024u64),
+        None => (unit.strip_suffix('b').unwrap_or(&unit), 1000),
+    };
+    let exponent = match prefix {
+        "" => 0,
+        "k" => 1,
+        "m" => 2,
+        "g" => 3,
+        "t" => 4,
+        "p" => 5,
+        "e" => 6,
+        _ => return None,
+    };
+    // "iB" alone isn't a unit
+    if exponent == 0 && base == 1024 { return None; }
+    Some(base.pow(exponent))
+}
+
+/// A `--block-size`: sizes are printed as a number of blocks, rounded up like du does.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockSize {
+    pub size: u64,
+    /// Printed after every size, if the block size was given as a unit only, like `-BM`
+    pub suffix: String,
+}
+
+impl BlockSize {
+    pub fn new(size: u64) -> Self {
+        Self { size, suffix: String::new() }
+    }
+
+    pub fn format(&self, bytes: u64) -> String {
+        format!("{}{}", bytes.div_ceil(self.size), self.suffix)
+    }
+}
+
+/// Parse a `--block-size` the way du does, see [`parse_du_size`].
+pub fn parse_block_size(spec: &str) -> Result<BlockSize, String> {
+    let (size, suffix) = parse_du_size(spec, "block size")?;
+    if size == 0 {
+        return Err(format!("invalid block size '{}'", spec));
+    }
+    Ok(BlockSize { size, suffix })
+}
+
+/// Parse a `--threshold` the way du does: a size like [`parse_block_size`] takes, negative to mean
+/// "at most" instead of "at least".
+pub fn parse_du_threshold(threshold: &str) -> Result<Threshold, String> {
+    match threshold.strip_prefix('-') {
+        // du doesn't take -0 either
+        Some(size) => match parse_du_size(size, "threshold")? {
+            (0, _) => Err(format!("invalid threshold '{}'", threshold)),
+            (size, _) => Ok(Threshold::AtMost(size)),
+        },
+        None => parse_du_size(threshold, "threshold").map(|(size, _)| Threshold::AtLeast(size)),
+    }
+}
+
+/// Parse a size in bytes the way du does: `K`, `M`, `G`, ... and `KiB`, `MiB`, ... are powers of 1024,
+/// `KB`, `MB`, ... powers of 1000, optionally preceded by a whole number.
+///
+/// Also returns the unit to print after sizes, if `spec` is a unit only, like `M`. `what` names the size
+/// in errors.
+fn parse_du_size(spec: &str, what: &str) -> Result<(u64, String), String> {
+    if spec.is_empty() {
+        return Err(format!("invalid {} '{}'", what, spec));
+    }
+    let split = spec.find(|c: char| !c.is_ascii_digit()).unwrap_or(spec.len());
+    let (number, unit) = spec.split_at(split);
+    let mut chars = unit.chars();
+    let (multiplier, suffix) = match chars.next() {
+        None => (1, String::new()),
+        Some(prefix) => {
+            let exponent = "kmgtpe".find(prefix.to_ascii_lowercase())
+                .ok_or_else(|| format!("invalid {} '{}'", what, spec))? as u32 + 1;
+            let prefix = prefix.to_ascii_uppercase();
+            match chars.as_str() {
+                "" => (1024u64.pow(exponent), prefix.to_string()),
+                "iB" | "ib" => (1024u64.pow(exponent), format!("{}iB", prefix)),
+                "B" | "b" if prefix == 'K' => (1000u64.pow(exponent), "kB".to_string()),
+                "B" | "b" => (1000u64.pow(exponent), format!("{}B", prefix)),
+                _ => return Err(format!("invalid {} '{}'", what, spec)),
+            }
+        },
+    };
+    let number = if number.is_empty() {
+        1
+    } else {
+        number.parse::<u64>().map_err(|_| format!("invalid {} '{}'", what, spec))?
+    };
+    let size = number.checked_mul(multiplier).ok_or_else(|| format!("{} '{}' is too large", what, spec))?;
+    // like du, only a bare unit is repeated in the output
+    let suffix = if number == 1 && split == 0 { suffix } else { String::new() };
+    Ok((size, suffix))
+}
+
+/// Format `bytes` like `du --si`: powers of 1000, one decimal below 10, always rounded up.
+pub fn format_si(bytes: u64) -> String {
+    format_rounded_up(bytes, 1000, ["", "k", "M", "G", "T", "P", "E"])
+}
+
+/// Format `bytes` like `du -h`: the same as [`format_si`], but in powers of 1024.
+pub fn format_binary(bytes: u64) -> String {
+    format_rounded_up(bytes, 1024, ["", "K", "M", "G", "T", "P", "E"])
+}
+
+fn format_rounded_up(bytes: u64, base: u128, units: [&str; 7]) -> String {
+    let bytes = u128::from(bytes);
+    let mut exponent = 0;
+    let mut divisor = 1u128;
+    while bytes >= divisor * base && exponent < units.len() - 1 {
+        divisor *= base;
+        exponent += 1;
+    }
+    if exponent == 0 {
+        return bytes.to_string();
+    }
+    let tenths = (bytes * 10).div_ceil(divisor);
+    if tenths < 100 {
+        return format!("{}.{}{}", tenths / 10, tenths % 10, units[exponent]);
+    }
+    let whole = bytes.div_ceil(divisor);
+    // rounding up may carry over into the next unit
+    if whole >= base && exponent < units.len() - 1 {
+        return format!("1.0{}", units[exponent + 1]);
+    }
+    format!("{}{}", whole, units[exponent])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes() {
+        assert_eq!(parse_size("100"), Ok(100));
+        assert_eq!(parse_size("100M"), Ok(100_000_000));
+        assert_eq!(parse_size("100mb"), Ok(100_000_000));
+        assert_eq!(parse_size("4KiB"), Ok(4096));
+        assert_eq!(parse_size("1.5GiB"), Ok(1_610_612_736));
+        assert_eq!(parse_size(" 2 k "), Ok(2000));
+    }
+
+    #[test]
+    fn invalid_sizes() {
+        assert!(parse_size("").is_err());
+        assert!(parse_size("M").is_err());
+        assert!(parse_size("1.2.3").is_err());
+        assert!(parse_size("10x").is_err());
+        assert!(parse_size("10iB").is_err());
+    }
+
+    #[test]
+    fn too_large_sizes() {
+        // 2e19 no longer fits in a u64 itself, so this goes through the float path
+        assert_eq!(parse_size("20000000000000000000"), Err("size '20000000000000000000' is too large".to_string()));
+        assert_eq!(parse_size("20000000000000000k"), Err("size '20000000000000000k' is too large".to_string()));
+        assert!(parse_size("16EiB").is_err());
+        assert_eq!(parse_size("15EiB"), Ok(15 << 60));
+    }
+
+    #[test]
+    fn units() {
+        assert_eq!(unit_multiplier(""), Some(1));
+        assert_eq!(unit_multiplier("B"), Some(1));
+        assert_eq!(unit_multiplier("k"), Some(1000));
+        assert_eq!(unit_multiplier("KB"), Some(1000));
+        assert_eq!(unit_multiplier("Ki"), None);
+        assert_eq!(unit_multiplier("KiB"), Some(1024));
+        assert_eq!(unit_multiplier("eib"), Some(1 << 60));
+        assert_eq!(unit_multiplier("iB"), None);
+        assert_eq!(unit_multiplier("ib"), None);
+        assert_eq!(unit_multiplier("x"), None);
+    }
+
+    #[test]
+    fn thresholds() {
+        assert_eq!(parse_threshold("100M"), Ok(Threshold::AtLeast(100_000_000)));
+        assert_eq!(parse_threshold("-1.5GiB"), Ok(Threshold::AtMost(1_610_612_736)));
+        assert_eq!(parse_threshold(" -1k"), Ok(Threshold::AtMost(1000)));
+        assert!(parse_threshold("").is_err());
+        assert!(parse_threshold("-").is_err());
+        assert!(Threshold::AtLeast(10).admits(10));
+        assert!(!Threshold::AtLeast(10).admits(9));
+        assert!(Threshold::AtMost(10).admits(10));
+        assert!(!Threshold::AtMost(10).admits(11));
+    }
+
+    #[test]
+    fn du_sizes() {
+        assert_eq!(parse_block_size("1K"), Ok(BlockSize { size: 1024, suffix: String::new() }));
+        assert_eq!(parse_block_size("M"), Ok(BlockSize { size: 1 << 20, suffix: "M".to_string() }));
+        assert_eq!(parse_block_size("kB"), Ok(BlockSize { size: 1000, suffix: "kB".to_string() }));
+        assert_eq!(parse_block_size("GiB"), Ok(BlockSize { size: 1 << 30, suffix: "GiB".to_string() }));
+        assert!(parse_block_size("0").is_err());
+        assert!(parse_block_size("").is_err());
+        assert!(parse_block_size("1.5M").is_err());
+        assert_eq!(parse_du_threshold("1MB"), Ok(Threshold::AtLeast(1_000_000)));
+        assert_eq!(parse_du_threshold("-1KiB"), Ok(Threshold::AtMost(1024)));
+        assert!(parse_du_threshold("-0").is_err());
+        assert!(parse_du_threshold("20000000000E").is_err());
+    }
+}
@@ -0,0 +1,107 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use serde::Serialize;
+use crate::{DirUsage, FileUsage, ScanObserver, Usage};
+
+/// Keeps the largest files and directories reported by a scan.
+///
+/// Only `n` of each are held at any time, so unlike [`TreeBuilder`](crate::TreeBuilder)
+/// this works for trees of any size.
+#[derive(Debug)]
+pub struct TopN {
+    n: usize,
+    key: fn(&Usage) -> u64,
+    files: BinaryHeap<Reverse<Ranked<FileUsage>>>,
+    dirs: BinaryHeap<Reverse<Ranked<DirUsage>>>,
+}
+
+/// The largest files and directories of a scan, largest first, see [`TopN`].
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Top {
+    pub files: Vec<FileUsage>,
+    pub dirs: Vec<DirUsage>,
+}
+
+impl TopN {
+    /// Keep the `n` largest files and directories, by apparent size if `apparent_size` is set
+    /// and by allocated size otherwise.
+    pub fn new(n: usize, apparent_size: bool) -> Self {
+        if apparent_size {
+            Self::by_key(n, |usage| usage.apparent)
+        } else {
+            Self::by_key(n, |usage| usage.allocated)
+        }
+    }
+
+    /// Keep the `n` largest files and directories, by whatever `key` says.
+    pub fn by_key(n: usize, key: fn(&Usage) -> u64) -> Self {
+        Self {
+            n,
+            key,
+            files: BinaryHeap::with_capacity(n + 1),
+            dirs: BinaryHeap::with_capacity(n + 1),
+        }
+    }
+
+    pub fn into_top(self) -> Top {
+        Top {
+            files: into_sorted(self.files),
+            dirs: into_sorted(self.dirs),
+        }
+    }
+}
+
+impl ScanObserver for TopN {
+    fn on_dir(&mut self, dir: &DirUsage) {
+        let size = (self.key)(&dir.usage);
+        push(&mut self.dirs, self.n, size, dir);
+    }
+
+    fn on_file(&mut self, file: &FileUsage) {
+        let size = (self.key)(&file.usage);
+        push(&mut self.files, self.n, size, file);
+    }
+}
+
+/// Add `item` to `heap` if it is among the `n` largest so far.
+fn push<T: Clone>(heap: &mut BinaryHeap<Reverse<Ranked<T>>>, n: usize, size: u64, item: &T) {
+    if n == 0 { return; }
+    // only clone what actually makes it in, most entries don't
+    if heap.len() == n && heap.peek().is_some_and(|smallest| smallest.0.size >= size) { return; }
+    heap.push(Reverse(Ranked { size, item: item.clone() }));
+    if heap.len() > n {
+        heap.pop();
+    }
+}
+
+fn into_sorted<T>(heap: BinaryHeap<Reverse<Ranked<T>>>) -> Vec<T> {
+    // ascending order of Reverse is descending order of size
+    heap.into_sorted_vec().into_iter().map(|ranked| ranked.0.item).collect()
+}
+
+/// An item ordered by its size only.
+#[derive(Debug)]
+struct Ranked<T> {
+    size: u64,
+    item: T,
+}
+
+impl<T> PartialEq for Ranked<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.size == other.size
+    }
+}
+
+impl<T> Eq for Ranked<T> {}
+
+impl<T> PartialOrd for Ranked<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for Ranked<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.size.cmp(&other.size)
+    }
+}
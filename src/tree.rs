@@ -0,0 +1,197 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use serde::{Serialize, Serializer};
+use crate::{DirUsage, FileUsage, ScanError, ScanObserver, ScanOperation, Usage};
+
+/// Whether a [`Node`] is a directory or a file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeKind {
+    Dir,
+    File,
+    /// A scanned path that couldn't be read at all, [`Node::errors`] says why
+    Error,
+}
+
+/// A scanned entry, together with everything below it.
+#[derive(Debug, Clone, Serialize)]
+pub struct Node {
+    /// File name, or the full path for the scanned paths themselves
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: NodeKind,
+    #[serde(flatten)]
+    pub usage: Usage,
+    /// Errors that kept entries directly in this directory from being counted,
+    /// or in directories below it that weren't reported because of [`ScanOptions::max_depth`](crate::ScanOptions::max_depth)
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<Node>,
+    /// Whether part of a directory wasn't counted, see [`DirUsage::incomplete`]
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub incomplete: bool,
+}
+
+/// Collects everything reported by a scan into a tree of [`Node`]s.
+///
+/// Unlike the scan itself, this keeps the whole tree in memory.
+#[derive(Debug, Default)]
+pub struct TreeBuilder {
+    /// Nodes whose parent directory isn't done yet, by the path of that parent
+    pending: HashMap<PathBuf, Vec<Node>>,
+    /// Errors for directories that aren't done yet
+    errors: HashMap<PathBuf, Vec<String>>,
+    /// The scanned paths, with their index
+    roots: Vec<(usize, Node)>,
+}
+
+impl TreeBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The scanned paths, in the order they were passed in.
+    pub fn into_roots(mut self) -> Vec<Node> {
+        self.roots.sort_by_key(|(root, _)| *root);
+        self.roots.into_iter().map(|(_, node)| node).collect()
+    }
+
+    fn push(&mut self, path: &Path, root: Option<usize>, node: Node) {
+        match (root, path.parent()) {
+            (Some(root), _) => self.roots.push((root, node)),
+            (None, Some(parent)) => self.pending.entry(parent.to_path_buf()).or_default().push(node),
+            // only the scanned paths have no parent
+            (None, None) => {},
+        }
+    }
+}
+
+fn node_name(path: &Path, depth: usize) -> String {
+    match path.file_name() {
+        Some(name) if depth > 0 => name.to_string_lossy().into_owned(),
+        _ => path.to_string_lossy().into_owned(),
+    }
+}
+
+impl ScanObserver for TreeBuilder {
+    fn on_dir(&mut self, dir: &DirUsage) {
+        let mut errors = self.errors.remove(&dir.path).unwrap_or_default();
+        // everything below is done, so errors still waiting there are for directories that weren't reported
+        let below: Vec<PathBuf> = self.errors.keys().filter(|path| path.starts_with(&dir.path)).cloned().collect();
+        for path in below {
+            errors.extend(self.errors.remove(&path).unwrap_or_default());
+        }
+        let mut node = Node {
+            name: node_name(&dir.path, dir.depth),
+            kind: NodeKind::Dir,
+            usage: dir.usage,
+            errors,
+            children: self.pending.remove(&dir.path).unwrap_or_default(),
+            incomplete: dir.incomplete,
+        };
+        // entries finish in no particular order, make the output reproducible
+        node.children.sort_by(|a, b| a.name.cmp(&b.name));
+        self.push(&dir.path, dir.root, node);
+    }
+
+    fn on_file(&mut self, file: &FileUsage) {
+        let node = Node {
+            name: node_name(&file.path, file.depth),
+            kind: NodeKind::File,
+            usage: file.usage,
+            errors: Vec::new(),
+            children: Vec::new(),
+            incomplete: false,
+        };
+        self.push(&file.path, file.root, node);
+    }
+
+    fn on_error(&mut self, err: &ScanError) {
+        // a scanned path that can't be read has no node to hold its error, so it gets one of its own
+        if let (ScanOperation::Metadata, Some(root)) = (err.operation, err.root) {
+            self.roots.push((root, Node {
+                name: err.path.to_string_lossy().into_owned(),
+                kind: NodeKind::Error,
+                usage: Usage::default(),
+                errors: vec![err.to_string()],
+                children: Vec::new(),
+                incomplete: false,
+            }));
+            return;
+        }
+        // a directory that can't be listed owns its error, anything else belongs to its parent
+        let dir = match err.operation {
+            ScanOperation::ReadDir => Some(err.path.as_path()),
+            ScanOperation::Metadata => err.path.parent(),
+            ScanOperation::Task => None,
+        };
+        if let Some(dir) = dir {
+            self.errors.entry(dir.to_path_buf()).or_default().push(err.to_string());
+        }
+    }
+}
+
+/// Serialize a path as a string, replacing anything that isn't valid UTF-8.
+pub(crate) fn serialize_path<S: Serializer>(path: &Path, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&path.to_string_lossy())
+}
+
+/// Serialize a time as whole seconds since the Unix epoch, earlier times as 0.
+pub(crate) fn serialize_time<S: Serializer>(time: &Option<SystemTime>, serializer: S) -> Result<S::Ok, S::Error> {
+    let secs = time.and_then(|time| time.duration_since(UNIX_EPOCH).ok()).map_or(0, |since| since.as_secs());
+    serializer.serialize_u64(secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io;
+
+    fn dir(path: &str, depth: usize, root: Option<usize>) -> DirUsage {
+        DirUsage {
+            path: PathBuf::from(path),
+            depth,
+            usage: Usage::default(),
+            modified: None,
+            own_usage: Usage::default(),
+            owner: None,
+            root,
+            incomplete: false,
+        }
+    }
+
+    fn error(path: &str, root: Option<usize>, operation: ScanOperation) -> ScanError {
+        ScanError { path: PathBuf::from(path), root, operation, error: io::ErrorKind::PermissionDenied.into() }
+    }
+
+    #[test]
+    fn unreadable_path() {
+        let mut tree = TreeBuilder::new();
+        tree.on_error(&error("missing", Some(1), ScanOperation::Metadata));
+        tree.on_dir(&dir("a", 0, Some(0)));
+        let roots = tree.into_roots();
+        assert_eq!(roots.iter().map(|node| (node.name.as_str(), node.kind)).collect::<Vec<_>>(), [("a", NodeKind::Dir), ("missing", NodeKind::Error)]);
+        assert_eq!(roots[1].errors, ["cannot access 'missing': permission denied"]);
+    }
+
+    #[test]
+    fn errors_below_max_depth() {
+        let mut tree = TreeBuilder::new();
+        // with max_depth 1, a/b/c and a/b/c/d aren't reported
+        tree.on_error(&error("a/b/c", None, ScanOperation::ReadDir));
+        tree.on_error(&error("a/b/c/d/f", None, ScanOperation::Metadata));
+        tree.on_error(&error("a/e", None, ScanOperation::ReadDir));
+        tree.on_dir(&dir("a/b", 1, None));
+        tree.on_dir(&dir("a/e", 1, None));
+        tree.on_dir(&dir("a", 0, Some(0)));
+        let roots = tree.into_roots();
+        let b = &roots[0].children[0];
+        let mut errors = b.errors.clone();
+        errors.sort();
+        assert_eq!(errors, ["cannot access 'a/b/c/d/f': permission denied", "cannot read directory 'a/b/c': permission denied"]);
+        assert_eq!(roots[0].children[1].errors, ["cannot read directory 'a/e': permission denied"]);
+        assert!(roots[0].errors.is_empty());
+    }
+}
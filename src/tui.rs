@@ -0,0 +1,320 @@
+//! `rdu --interactive`: browse the result of a scan, like ncdu.
+
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use humansize::{format_size, FormatSizeOptions};
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Modifier, Style};
+use ratatui::widgets::{List, ListItem, ListState, Paragraph};
+use ratatui::{DefaultTerminal, Frame};
+use rdu::{Node, NodeKind, Scanner, TreeBuilder, Usage};
+use tokio::sync::mpsc;
+use crate::Opts;
+
+const BAR_WIDTH: usize = 20;
+const HELP: &str = "↑/↓ select  →/enter open  ←/backspace up  r rescan  q quit";
+/// How often the thread reading terminal events checks whether it is still needed
+const EVENT_POLL: Duration = Duration::from_millis(100);
+
+/// Show `roots` until the user quits, sized and sorted like `opts` says. Directories can be rescanned with `scanner`.
+pub async fn run(scanner: &Scanner, roots: Vec<Node>, opts: &Opts) -> io::Result<()> {
+    let mut browser = Browser::new(roots, opts);
+    let mut terminal = ratatui::init();
+    let result = browser.run(&mut terminal, scanner).await;
+    ratatui::restore();
+    result
+}
+
+struct Browser<'a> {
+    roots: Vec<Node>,
+    /// Index of every directory entered, starting in `roots`
+    location: Vec<usize>,
+    list: ListState,
+    opts: &'a Opts,
+    status: String,
+}
+
+impl<'a> Browser<'a> {
+    fn new(mut roots: Vec<Node>, opts: &'a Opts) -> Self {
+        sort_by_size(&mut roots, opts);
+        // with a single scanned path, there is no point in starting at the list of scanned paths
+        let location = if roots.len() == 1 && roots[0].kind == NodeKind::Dir { vec![0] } else { Vec::new() };
+        Self {
+            roots,
+            location,
+            list: ListState::default().with_selected(Some(0)),
+            opts,
+            status: String::new(),
+        }
+    }
+
+    async fn run(&mut self, terminal: &mut DefaultTerminal, scanner: &Scanner) -> io::Result<()> {
+        let mut events = spawn_events();
+        loop {
+            terminal.draw(|frame| self.draw(frame))?;
+            let Some(event) = events.recv().await else { return Ok(()) };
+            let Event::Key(key) = event? else { continue };
+            if key.kind != KeyEventKind::Press { continue; }
+            self.status.clear();
+            match key.code {
+                _ if is_quit(key) => return Ok(()),
+                KeyCode::Up | KeyCode::Char('k') => self.list.select_previous(),
+                KeyCode::Down | KeyCode::Char('j') => self.list.select_next(),
+                KeyCode::Home => self.list.select_first(),
+                KeyCode::End => self.list.select_last(),
+                KeyCode::Right | KeyCode::Enter | KeyCode::Char('l') => self.descend(),
+                KeyCode::Left | KeyCode::Backspace | KeyCode::Char('h') => self.ascend(),
+                KeyCode::Char('r') => {
+                    self.status = "rescanning... (q to stop)".to_string();
+                    terminal.draw(|frame| self.draw(frame))?;
+                    self.rescan(scanner, &mut events).await;
+                },
+                _ => {},
+            }
+        }
+    }
+
+    fn format_usage(&self, usage: &Usage) -> String {
+        if self.opts.inodes {
+            usage.inodes().to_string()
+        } else if self.opts.both {
+            format!("{} / {}", self.format_size(usage.allocated), self.format_size(usage.apparent))
+        } else {
+            self.format_size(self.opts.size(usage))
+        }
+    }
+
+    fn format_size(&self, size: u64) -> String {
+        let opts = self.opts;
+        // unlike in the text output, a plain number of bytes is hard to take in at a glance
+        if opts.si || opts.block_size.is_some() || opts.kilobytes || opts.megabytes {
+            opts.format_size(size)
+        } else {
+            format_size(size, FormatSizeOptions::default())
+        }
+    }
+
+    /// Entries of the directory currently shown.
+    fn entries(&self) -> &[Node] {
+        let mut entries = self.roots.as_slice();
+        for &index in &self.location {
+            entries = &entries[index].children;
+        }
+        entries
+    }
+
+    fn node_mut(&mut self, location: &[usize]) -> Option<&mut Node> {
+        let (first, rest) = location.split_first()?;
+        let mut node = &mut self.roots[*first];
+        for &index in rest {
+            node = &mut node.children[index];
+        }
+        Some(node)
+    }
+
+    fn path(&self, location: &[usize]) -> PathBuf {
+        let mut path = PathBuf::new();
+        let mut entries = self.roots.as_slice();
+        for &index in location {
+            // the scanned paths are named by their full path, so this also works for the first one
+            path.push(&entries[index].name);
+            entries = &entries[index].children;
+        }
+        path
+    }
+
+    fn descend(&mut self) {
+        let Some(selected) = self.list.selected() else { return };
+        match self.entries().get(selected) {
+            Some(node) if node.kind == NodeKind::Dir && !node.children.is_empty() => {},
+            _ => return,
+        }
+        self.location.push(selected);
+        self.list.select(Some(0));
+    }
+
+    fn ascend(&mut self) {
+        if self.location.len() == 1 && self.roots.len() == 1 { return; }
+        if let Some(index) = self.location.pop() {
+            self.list.select(Some(index));
+        }
+    }
+
+    /// Scan the directory currently shown again, or the selected path if at the list of scanned paths.
+    ///
+    /// Stops early if a key to quit is pressed meanwhile, leaving the sizes as they were.
+    async fn rescan(&mut self, scanner: &Scanner, events: &mut mpsc::UnboundedReceiver<io::Result<Event>>) {
+        let mut location = self.location.clone();
+        if location.is_empty() {
+            let Some(selected) = self.list.selected() else { return };
+            location.push(selected.min(self.roots.len().saturating_sub(1)));
+        }
+        let path = self.path(&location);
+        let mut builder = TreeBuilder::new();
+        let result = {
+            let scan = scanner.scan_with(&path, &mut builder);
+            tokio::pin!(scan);
+            loop {
+                tokio::select! {
+                    result = &mut scan => break result,
+                    Some(event) = events.recv() => {
+                        if let Ok(Event::Key(key)) = event {
+                            if key.kind == KeyEventKind::Press && is_quit(key) {
+                                scanner.stop_handle().stop();
+                            }
+                        }
+                    },
+                }
+            }
+        };
+        if result.interrupted {
+            self.status = format!("stopped rescanning '{}'", path.display());
+            return;
+        }
+        let Some(mut node) = builder.into_roots().pop().filter(|node| node.kind != NodeKind::Error) else {
+            self.status = format!("cannot rescan '{}'", path.display());
+            return;
+        };
+        sort_by_size(std::slice::from_mut(&mut node), self.opts);
+
+        // sizes change below, so the order of the entries along `location` may change too
+        let names = self.names(&location);
+        let selected_name = self.list.selected()
+            .and_then(|selected| self.entries().get(selected))
+            .map(|node| node.name.clone());
+
+        let Some(old) = self.node_mut(&location) else { return };
+        node.name = std::mem::take(&mut old.name);
+        let old_usage = std::mem::replace(old, node).usage;
+        for depth in 1..location.len() {
+            if let Some(ancestor) = self.node_mut(&location[..depth]) {
+                ancestor.usage = replace_usage(ancestor.usage, old_usage, result.total);
+            }
+        }
+        for (depth, name) in names.iter().enumerate() {
+            let opts = self.opts;
+            let siblings = match self.node_mut(&location[..depth]) {
+                Some(parent) => &mut parent.children,
+                None => &mut self.roots,
+            };
+            sort_by_size_shallow(siblings, opts);
+            location[depth] = siblings.iter().position(|node| &node.name == name).unwrap_or(0);
+        }
+        self.location = location[..self.location.len()].to_vec();
+        let selected = selected_name.and_then(|name| self.entries().iter().position(|node| node.name == name));
+        self.list.select(selected.or(Some(0)));
+
+        self.status = if result.is_complete() {
+            format!("rescanned '{}'", path.display())
+        } else {
+            format!("rescanned '{}' with {} errors", path.display(), result.errors.len())
+        };
+    }
+
+    /// Names of the entries along `location`.
+    fn names(&self, location: &[usize]) -> Vec<String> {
+        let mut names = Vec::with_capacity(location.len());
+        let mut entries = self.roots.as_slice();
+        for &index in location {
+            names.push(entries[index].name.clone());
+            entries = &entries[index].children;
+        }
+        names
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, body, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(0),
+            Constraint::Length(1),
+        ]).areas(frame.area());
+
+        let entries = self.entries();
+        let total = entries.iter().fold(Usage::default(), |total, node| total + node.usage);
+        let title = if self.location.is_empty() {
+            "scanned paths".to_string()
+        } else {
+            self.path(&self.location).display().to_string()
+        };
+        frame.render_widget(
+            Paragraph::new(format!("rdu  {}  ({})", title, self.format_usage(&total)))
+                .style(Style::new().add_modifier(Modifier::REVERSED)),
+            header,
+        );
+
+        let total = self.opts.size(&total);
+        let items: Vec<ListItem> = entries.iter().map(|node| {
+            let size = self.opts.size(&node.usage);
+            let share = if total == 0 { 0.0 } else { size as f64 / total as f64 };
+            let filled = (share * BAR_WIDTH as f64).round() as usize;
+            ListItem::new(format!(
+                "{:>10} {:>5.1}% [{}{}] {}{}{}",
+                self.format_usage(&node.usage),
+                share * 100.0,
+                "#".repeat(filled),
+                " ".repeat(BAR_WIDTH - filled),
+                if node.errors.is_empty() { "" } else { "! " },
+                node.name,
+                if node.kind == NodeKind::Dir { "/" } else { "" },
+            ))
+        }).collect();
+        let list = List::new(items).highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+        frame.render_stateful_widget(list, body, &mut self.list);
+
+        let status = if self.status.is_empty() { HELP } else { self.status.as_str() };
+        frame.render_widget(Paragraph::new(status), footer);
+    }
+}
+
+/// Read terminal events on a thread of their own, as reading them blocks. The thread ends once the receiver is dropped.
+fn spawn_events() -> mpsc::UnboundedReceiver<io::Result<Event>> {
+    let (tx, rx) = mpsc::unbounded_channel();
+    std::thread::spawn(move || {
+        while !tx.is_closed() {
+            let event = match event::poll(EVENT_POLL) {
+                Ok(false) => continue,
+                Ok(true) => event::read(),
+                Err(err) => Err(err),
+            };
+            let failed = event.is_err();
+            if tx.send(event).is_err() || failed { break; }
+        }
+    });
+    rx
+}
+
+/// Whether `key` quits the browser, or stops a rescan. Ctrl-C is a key like any other in raw mode.
+fn is_quit(key: KeyEvent) -> bool {
+    match key.code {
+        KeyCode::Char('q') | KeyCode::Esc => true,
+        KeyCode::Char('c') => key.modifiers.contains(KeyModifiers::CONTROL),
+        _ => false,
+    }
+}
+
+/// Sort `nodes` and everything below them, largest first by [`Opts::size`].
+fn sort_by_size(nodes: &mut [Node], opts: &Opts) {
+    sort_by_size_shallow(nodes, opts);
+    for node in nodes {
+        sort_by_size(&mut node.children, opts);
+    }
+}
+
+/// Sort `nodes` largest first, but not the entries below them.
+fn sort_by_size_shallow(nodes: &mut [Node], opts: &Opts) {
+    nodes.sort_by_key(|node| std::cmp::Reverse(opts.size(&node.usage)));
+}
+
+/// `total` with the part that was `old` replaced by `new`.
+fn replace_usage(total: Usage, old: Usage, new: Usage) -> Usage {
+    Usage {
+        apparent: total.apparent - old.apparent + new.apparent,
+        allocated: total.allocated - old.allocated + new.allocated,
+        files: total.files - old.files + new.files,
+        dirs: total.dirs - old.dirs + new.dirs,
+        symlinks: total.symlinks - old.symlinks + new.symlinks,
+        others: total.others - old.others + new.others,
+    }
+}
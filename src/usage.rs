@@ -0,0 +1,86 @@
+use serde::Serialize;
+use std::fs::Metadata;
+use std::ops::{Add, AddAssign};
+#[cfg(all(not(target_os = "hermit"), unix))]
+use std::os::unix::fs::MetadataExt as MetadataExtUnix;
+
+/// Space used by a file or a whole directory tree, in bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize)]
+pub struct Usage {
+    /// Sum of file lengths, as reported by `ls -l`
+    #[serde(rename = "apparent_size")]
+    pub apparent: u64,
+    /// Space actually allocated on disk, as seen by `df`.
+    /// Smaller than `apparent` for sparse files, usually larger for small files.
+    #[serde(rename = "allocated_size")]
+    pub allocated: u64,
+    /// Number of regular files counted
+    pub files: u64,
+    /// Number of directories counted, including the directory itself
+    pub dirs: u64,
+    /// Number of symlinks counted, that weren't followed
+    pub symlinks: u64,
+    /// Number of anything else counted: fifos, sockets, device files, ...
+    pub others: u64,
+}
+
+impl Usage {
+    /// Space used by the single entry `meta` describes, counted as an entry of its type.
+    pub fn of(meta: &Metadata) -> Self {
+        Self {
+            apparent: meta.len(),
+            allocated: allocated_size(meta),
+            ..Self::entry(meta)
+        }
+    }
+
+    /// Counts a single entry of the type `meta` describes, but none of the space it uses.
+    pub fn entry(meta: &Metadata) -> Self {
+        let file_type = meta.file_type();
+        if file_type.is_file() {
+            Self { files: 1, ..Self::default() }
+        } else if file_type.is_dir() {
+            Self { dirs: 1, ..Self::default() }
+        } else if file_type.is_symlink() {
+            Self { symlinks: 1, ..Self::default() }
+        } else {
+            Self { others: 1, ..Self::default() }
+        }
+    }
+
+    /// Number of entries counted, of any type.
+    pub fn inodes(&self) -> u64 {
+        self.files + self.dirs + self.symlinks + self.others
+    }
+}
+
+#[cfg(all(not(target_os = "hermit"), unix))]
+fn allocated_size(meta: &Metadata) -> u64 {
+    // st_blocks is always in 512 byte units, regardless of the filesystem block size
+    meta.blocks() * 512
+}
+
+#[cfg(not(all(not(target_os = "hermit"), unix)))]
+fn allocated_size(meta: &Metadata) -> u64 {
+    meta.len()
+}
+
+impl Add for Usage {
+    type Output = Usage;
+
+    fn add(mut self, rhs: Usage) -> Usage {
+        self += rhs;
+        self
+    }
+}
+
+impl AddAssign for Usage {
+    fn add_assign(&mut self, rhs: Usage) {
+        self.apparent += rhs.apparent;
+        self.allocated += rhs.allocated;
+        self.files += rhs.files;
+        self.dirs += rhs.dirs;
+        self.symlinks += rhs.symlinks;
+        self.others += rhs.others;
+    }
+}
@@ -0,0 +1,424 @@
+//! `rdu --gnu` against the output of GNU du 9.1 for the same trees.
+//!
+//! Directories take a different amount of space on every filesystem, so the recorded sizes leave
+//! them out and the tests add the size of every directory below a path at runtime. Everything else
+//! is sized explicitly, and mostly compared by apparent size for the same reason.
+//!
+//! Run with `DU=du` to check the recorded output against the du installed instead.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// A tree of files in a temporary directory, removed again on drop.
+struct Fixture {
+    root: PathBuf,
+}
+
+impl Fixture {
+    fn new() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        let name = format!("rdu-gnu-{}-{}", std::process::id(), NEXT.fetch_add(1, Ordering::Relaxed));
+        let root = std::env::temp_dir().join(name);
+        fs::create_dir_all(&root).unwrap();
+        Self { root }
+    }
+
+    /// The tree most tests start from: two levels of directories, an empty one and some files.
+    fn basic() -> Self {
+        let fixture = Self::new();
+        fixture.file("t/a/f1", 5);
+        fixture.file("t/a/b/f2", 10000);
+        fixture.dir("t/c");
+        fixture.file("t/top", 100);
+        fixture
+    }
+
+    fn path(&self, path: &str) -> PathBuf {
+        self.root.join(path)
+    }
+
+    fn dir(&self, path: &str) {
+        fs::create_dir_all(self.path(path)).unwrap();
+    }
+
+    /// A file of `len` bytes, all of them written.
+    fn file(&self, path: &str, len: usize) {
+        let path = self.path(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, vec![b'x'; len]).unwrap();
+    }
+
+    /// A file of `len` bytes, none of them written.
+    fn sparse(&self, path: &str, len: u64) {
+        let path = self.path(path);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::File::create(path).unwrap().set_len(len).unwrap();
+    }
+
+    fn hardlink(&self, original: &str, link: &str) {
+        fs::hard_link(self.path(original), self.path(link)).unwrap();
+    }
+
+    fn symlink(&self, target: &str, link: &str) {
+        std::os::unix::fs::symlink(target, self.path(link)).unwrap();
+    }
+
+    /// Apparent size of the directory `path` and all directories below it, 0 if it isn't one.
+    fn dir_sizes(&self, path: &str) -> u64 {
+        fn walk(path: &Path) -> u64 {
+            match fs::symlink_metadata(path) {
+                Ok(meta) if meta.is_dir() => {
+                    let below: u64 = fs::read_dir(path).unwrap().map(|entry| walk(&entry.unwrap().path())).sum();
+                    meta.len() + below
+                },
+                _ => 0,
+            }
+        }
+        walk(&self.path(path))
+    }
+
+    /// An expected line of `-b` output: `recorded` bytes plus the directories below `path`.
+    fn bytes(&self, recorded: u64, path: &str) -> String {
+        format!("{}\t{}", recorded + self.dir_sizes(path), path)
+    }
+
+    /// Run `rdu --gnu` with `args` in the fixture, or the du given by `$DU`.
+    fn du<I: AsRef<OsStr>>(&self, args: impl IntoIterator<Item = I>) -> Output {
+        let mut command = match std::env::var_os("DU") {
+            Some(du) => Command::new(du),
+            None => {
+                let mut command = Command::new(env!("CARGO_BIN_EXE_rdu"));
+                command.arg("--gnu");
+                command
+            },
+        };
+        command.args(args).current_dir(&self.root).output().unwrap()
+    }
+
+    /// Lines printed by [`Fixture::du`], sorted, as rdu lists the directories in a path in no particular order.
+    fn lines<I: AsRef<OsStr>>(&self, args: impl IntoIterator<Item = I>) -> Vec<String> {
+        sorted(self.lines_in_order(args))
+    }
+
+    /// Lines printed by [`Fixture::du`], as they were printed.
+    fn lines_in_order<I: AsRef<OsStr>>(&self, args: impl IntoIterator<Item = I>) -> Vec<String> {
+        let output = self.du(args);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        String::from_utf8(output.stdout).unwrap().lines().map(str::to_string).collect()
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+fn sorted(mut lines: Vec<String>) -> Vec<String> {
+    lines.sort();
+    lines
+}
+
+#[test]
+fn directories() {
+    let fx = Fixture::basic();
+    assert_eq!(fx.lines(["-b", "t"]), sorted(vec![
+        fx.bytes(10000, "t/a/b"),
+        fx.bytes(10005, "t/a"),
+        fx.bytes(0, "t/c"),
+        fx.bytes(10105, "t"),
+    ]));
+}
+
+#[test]
+fn all() {
+    let fx = Fixture::basic();
+    assert_eq!(fx.lines(["-ab", "t"]), sorted(vec![
+        fx.bytes(10000, "t/a/b/f2"),
+        fx.bytes(10000, "t/a/b"),
+        fx.bytes(5, "t/a/f1"),
+        fx.bytes(10005, "t/a"),
+        fx.bytes(0, "t/c"),
+        fx.bytes(100, "t/top"),
+        fx.bytes(10105, "t"),
+    ]));
+}
+
+#[test]
+fn summarize_and_total() {
+    let fx = Fixture::basic();
+    let total = 10005 + fx.dir_sizes("t/a") + fx.dir_sizes("t/c");
+    assert_eq!(fx.lines(["-sbc", "t/a", "t/c"]), sorted(vec![
+        fx.bytes(10005, "t/a"),
+        fx.bytes(0, "t/c"),
+        format!("{}\ttotal", total),
+    ]));
+}
+
+#[test]
+fn max_depth() {
+    let fx = Fixture::basic();
+    assert_eq!(fx.lines(["-b", "-d", "1", "t"]), sorted(vec![
+        fx.bytes(10005, "t/a"),
+        fx.bytes(0, "t/c"),
+        fx.bytes(10105, "t"),
+    ]));
+    assert_eq!(fx.lines(["-ab", "--max-depth=1", "t"]), sorted(vec![
+        fx.bytes(10005, "t/a"),
+        fx.bytes(0, "t/c"),
+        fx.bytes(100, "t/top"),
+        fx.bytes(10105, "t"),
+    ]));
+}
+
+#[test]
+fn null_terminated() {
+    let fx = Fixture::basic();
+    let output = fx.du(["-0b", "t/top", "t/a/f1"]);
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), ["100\tt/top\0", "5\tt/a/f1\0"].concat());
+}
+
+#[test]
+fn hardlinks_count_once() {
+    let fx = Fixture::new();
+    fx.file("t/h1", 1000);
+    fx.hardlink("t/h1", "t/h2");
+    assert_eq!(fx.lines(["-sb", "t"]), vec![fx.bytes(1000, "t")]);
+    assert_eq!(fx.lines(["-lsb", "t"]), vec![fx.bytes(2000, "t")]);
+    assert_eq!(fx.lines(["-sb", "--count-links", "t"]), vec![fx.bytes(2000, "t")]);
+}
+
+#[test]
+fn hardlinks_between_paths() {
+    let fx = Fixture::new();
+    fx.file("t/a/f", 20000);
+    fx.file("u/g", 10);
+    fx.hardlink("t/a/f", "u/hl");
+    // counted for the first path given
+    assert_eq!(fx.lines(["-sb", "t", "u"]), sorted(vec![fx.bytes(20000, "t"), fx.bytes(10, "u")]));
+    assert_eq!(fx.lines(["-sb", "u", "t"]), sorted(vec![fx.bytes(0, "t"), fx.bytes(20010, "u")]));
+    assert_eq!(fx.lines(["-b", "u", "t"]), sorted(vec![fx.bytes(0, "t/a"), fx.bytes(0, "t"), fx.bytes(20010, "u")]));
+}
+
+#[test]
+fn paths_in_order() {
+    let fx = Fixture::new();
+    fx.file("a/x/f", 10);
+    fx.file("b/y/g", 20);
+    // every path is done, and printed, before the next one is started
+    assert_eq!(fx.lines_in_order(["-b", "a", "b"]), vec![
+        fx.bytes(10, "a/x"),
+        fx.bytes(10, "a"),
+        fx.bytes(20, "b/y"),
+        fx.bytes(20, "b"),
+    ]);
+    assert_eq!(fx.lines_in_order(["-sb", "b", "a/x/f", "a"]), vec![
+        fx.bytes(20, "b"),
+        "10\ta/x/f".to_string(),
+        format!("{}\ta", fx.dir_sizes("a")),
+    ]);
+}
+
+#[test]
+fn repeated_paths() {
+    let fx = Fixture::basic();
+    // like a hardlink, a path given again or inside an earlier one is only counted and printed the first time
+    assert_eq!(fx.lines_in_order(["-sb", "t", "t"]), vec![fx.bytes(10105, "t")]);
+    assert_eq!(fx.lines_in_order(["-sbc", "t", "t/a"]), vec![fx.bytes(10105, "t"), format!("{}\ttotal", 10105 + fx.dir_sizes("t"))]);
+    assert_eq!(fx.lines_in_order(["-sb", "t", "t/top"]), vec![fx.bytes(10105, "t")]);
+    assert_eq!(fx.lines_in_order(["-sb", "t/a", "t"]), vec![
+        fx.bytes(10005, "t/a"),
+        format!("{}\tt", 100 + fx.dir_sizes("t") - fx.dir_sizes("t/a")),
+    ]);
+}
+
+#[test]
+fn symlinks_count_their_own_size() {
+    let fx = Fixture::basic();
+    fx.symlink("a", "t/link");
+    fx.symlink("does/not/exist", "t/dangling");
+    assert_eq!(fx.lines(["-ab", "-d", "1", "t"]), sorted(vec![
+        fx.bytes(10005, "t/a"),
+        fx.bytes(0, "t/c"),
+        fx.bytes(14, "t/dangling"),
+        fx.bytes(1, "t/link"),
+        fx.bytes(100, "t/top"),
+        fx.bytes(10120, "t"),
+    ]));
+}
+
+#[test]
+fn dereference() {
+    let fx = Fixture::new();
+    fx.file("outside/f", 300);
+    fx.file("t/f", 7);
+    fx.symlink("../outside", "t/link");
+    let outside = fx.dir_sizes("outside");
+    // -P is the default
+    assert_eq!(fx.lines(["-sb", "t"]), vec![fx.bytes(17, "t")]);
+    assert_eq!(fx.lines(["-Psb", "t"]), vec![fx.bytes(17, "t")]);
+    assert_eq!(fx.lines(["-Lsb", "t"]), vec![fx.bytes(307 + outside, "t")]);
+    assert_eq!(fx.lines(["-L", "-P", "-sb", "t"]), vec![fx.bytes(17, "t")]);
+    // -H only follows the paths given
+    assert_eq!(fx.lines(["-sb", "t/link"]), vec!["10\tt/link".to_string()]);
+    assert_eq!(fx.lines(["-Hsb", "t/link"]), vec![format!("{}\tt/link", 300 + outside)]);
+    assert_eq!(fx.lines(["-Dsb", "t/link"]), vec![format!("{}\tt/link", 300 + outside)]);
+    assert_eq!(fx.lines(["-Hsb", "t"]), vec![fx.bytes(17, "t")]);
+}
+
+#[test]
+fn symlink_to_ancestor() {
+    let fx = Fixture::new();
+    fx.file("t/d/e/f", 5);
+    fx.symlink("..", "t/d/e/up");
+    // following up leads back to t/d, which is being counted already
+    let output = fx.du(["-Lb", "t"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let lines: Vec<String> = String::from_utf8(output.stdout).unwrap().lines().map(str::to_string).collect();
+    assert_eq!(sorted(lines), sorted(vec![fx.bytes(5, "t/d/e"), fx.bytes(5, "t/d"), fx.bytes(5, "t")]));
+    // du skips it silently
+    if std::env::var_os("DU").is_none() {
+        assert!(String::from_utf8(output.stderr).unwrap().contains("not following 't/d/e/up'"));
+    }
+}
+
+#[test]
+fn symlink_to_itself() {
+    let fx = Fixture::new();
+    fx.file("t/f", 5);
+    fx.symlink("self", "t/self");
+    fx.symlink("b", "t/a");
+    fx.symlink("a", "t/b");
+    // there is nothing to follow, so they can't be read
+    let output = fx.du(["-Lb", "t"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), format!("{}\n", fx.bytes(5, "t")));
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    for link in ["t/self", "t/a", "t/b"] {
+        assert!(stderr.contains(&format!("cannot access '{}': Too many levels of symbolic links", link)), "{}", stderr);
+    }
+    // not followed, they are just symlinks
+    assert_eq!(fx.lines(["-b", "t"]), vec![fx.bytes(11, "t")]);
+}
+
+#[test]
+fn sparse_files() {
+    let fx = Fixture::new();
+    fx.sparse("sparse", 1 << 20);
+    assert_eq!(fx.lines(["sparse"]), vec!["0\tsparse"]);
+    assert_eq!(fx.lines(["-b", "sparse"]), vec!["1048576\tsparse"]);
+    assert_eq!(fx.lines(["--apparent-size", "-h", "sparse"]), vec!["1.0M\tsparse"]);
+}
+
+#[test]
+fn human_readable() {
+    let fx = Fixture::new();
+    for len in [0, 1000, 1023, 1025, 10239, 10241, 999_999, 1_048_575] {
+        fx.sparse(&format!("f{}", len), len);
+    }
+    let files = ["f0", "f1000", "f1023", "f1025", "f10239", "f10241", "f999999", "f1048575"];
+    let sizes = |flag: &str| -> Vec<String> {
+        let output = fx.du(["--apparent-size", flag].into_iter().chain(files));
+        String::from_utf8(output.stdout).unwrap().lines().map(|line| line.split('\t').next().unwrap().to_string()).collect()
+    };
+    assert_eq!(sizes("-h"), ["0", "1000", "1023", "1.1K", "10K", "11K", "977K", "1.0M"]);
+    assert_eq!(sizes("--si"), ["0", "1.0k", "1.1k", "1.1k", "11k", "11k", "1.0M", "1.1M"]);
+}
+
+#[test]
+fn block_sizes() {
+    let fx = Fixture::new();
+    fx.sparse("f", 1_048_577);
+    let size = |flags: &[&str]| -> String {
+        let output = fx.du(["--apparent-size"].iter().chain(flags).chain(&["f"]));
+        String::from_utf8(output.stdout).unwrap().split('\t').next().unwrap().to_string()
+    };
+    assert_eq!(size(&[]), "1025");
+    assert_eq!(size(&["-k"]), "1025");
+    assert_eq!(size(&["-m"]), "2");
+    assert_eq!(size(&["-BM"]), "2M");
+    assert_eq!(size(&["-B1M"]), "2");
+    assert_eq!(size(&["-BKB"]), "1049kB");
+    assert_eq!(size(&["-BKiB"]), "1025KiB");
+    assert_eq!(size(&["-B3"]), "349526");
+    assert_eq!(size(&["--block-size=1K", "-h"]), "1.1M");
+    assert_eq!(size(&["-h", "-m"]), "2");
+    assert_eq!(size(&["-b"]), "1048577");
+}
+
+#[test]
+fn threshold() {
+    let fx = Fixture::basic();
+    assert_eq!(fx.lines(["-b", "-t", "9000", "t"]), sorted(vec![
+        fx.bytes(10000, "t/a/b"),
+        fx.bytes(10005, "t/a"),
+        fx.bytes(10105, "t"),
+    ]));
+    assert_eq!(fx.lines(["-ab", "--threshold=-100", "t/a/f1", "t/top", "t/a/b/f2"]), vec!["100\tt/top", "5\tt/a/f1"]);
+}
+
+#[test]
+fn threshold_units() {
+    let fx = Fixture::new();
+    for len in [1010, 1024, 1_020_000, 1_048_576] {
+        fx.sparse(&format!("f{}", len), len);
+    }
+    // like --block-size, K and M are powers of 1024, KB and MB powers of 1000
+    assert_eq!(fx.lines(["-b", "-t", "1K", "f1010", "f1024"]), vec!["1024\tf1024"]);
+    assert_eq!(fx.lines(["-b", "-t", "1M", "f1020000", "f1048576"]), vec!["1048576\tf1048576"]);
+    assert_eq!(fx.lines(["-b", "-t", "1MB", "f1020000", "f1048576"]), vec!["1020000\tf1020000", "1048576\tf1048576"]);
+    assert_eq!(fx.lines(["-b", "-t", "-1KiB", "f1010", "f1024", "f1020000"]), vec!["1010\tf1010", "1024\tf1024"]);
+    for invalid in ["1.5M", "-0", ""] {
+        assert!(!fx.du(["-b", "-t", invalid, "f1010"]).status.success(), "-t '{}'", invalid);
+    }
+}
+
+#[test]
+fn exclude() {
+    let fx = Fixture::basic();
+    let without_b = |path: &str| fx.dir_sizes(path) - fx.dir_sizes("t/a/b");
+    assert_eq!(fx.lines(["-b", "--exclude=b", "t"]), sorted(vec![
+        format!("{}\tt/a", 5 + without_b("t/a")),
+        fx.bytes(0, "t/c"),
+        format!("{}\tt", 105 + without_b("t")),
+    ]));
+}
+
+#[test]
+fn exclude_paths() {
+    let fx = Fixture::new();
+    fx.file("a/x/f", 10);
+    fx.file("b/y/g", 20);
+    // the paths given are skipped as well, not only what is below them
+    assert_eq!(fx.lines(["-b", "--exclude=a", "a", "b"]), sorted(vec![fx.bytes(20, "b/y"), fx.bytes(20, "b")]));
+    assert_eq!(fx.lines(["-sbc", "--exclude=*/x", "a/x", "b"]), sorted(vec![fx.bytes(20, "b"), format!("{}\ttotal", 20 + fx.dir_sizes("b"))]));
+}
+
+#[test]
+fn inodes() {
+    let fx = Fixture::basic();
+    fx.symlink("a", "t/link");
+    assert_eq!(fx.lines(["-s", "--inodes", "t"]), vec!["8\tt"]);
+    assert_eq!(fx.lines(["--inodes", "-d", "1", "t"]), vec!["1\tt/c", "4\tt/a", "8\tt"]);
+}
+
+#[test]
+fn missing_path() {
+    let fx = Fixture::basic();
+    let output = fx.du(["-sb", "t/top", "missing"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "100\tt/top\n");
+    assert!(String::from_utf8(output.stderr).unwrap().contains("cannot access 'missing'"));
+}
+
+#[test]
+fn run_as_du() {
+    if std::env::var_os("DU").is_some() { return; }
+    let fx = Fixture::basic();
+    fx.symlink(env!("CARGO_BIN_EXE_rdu"), "du");
+    let output = Command::new(fx.path("du")).args(["-sb", "t/top"]).current_dir(&fx.root).output().unwrap();
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "100\tt/top\n");
+}